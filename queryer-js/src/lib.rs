@@ -1,27 +1,249 @@
 use neon::prelude::*;
+use once_cell::sync::OnceCell;
+use queryer::{Cell, DataSet, ErrorKind, Param, QueryOptions};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// 查询结果的输出格式
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Csv,
+    Json,
+}
+
+/// query 的第二个参数：{ format, params, timeout }
+struct Options {
+    format: Format,
+    params: HashMap<String, Param>,
+    timeout: Option<Duration>,
+}
+
+/// 所有查询共享一个后台 tokio 运行时，不会阻塞 JS 的事件循环
+fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<&'static Runtime> {
+    static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+    RUNTIME.get_or_try_init(|| Runtime::new().or_else(|err| cx.throw_error(err.to_string())))
+}
 
 pub fn example_sql(mut cx: FunctionContext) -> JsResult<JsString> {
     Ok(cx.string(queryer::example_sql()))
 }
 
-fn query(mut cx: FunctionContext) -> JsResult<JsString> {
+/// query(sql, options?) => Promise<string | object[]>
+fn query(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let sql = cx.argument::<JsString>(0)?.value(&mut cx);
-    let output = match cx.argument::<JsString>(1) {
-        Ok(v) => v.value(&mut cx),
-        Err(_) => "csv".to_string(),
+    let Options {
+        format,
+        params,
+        timeout,
+    } = parse_options(&mut cx)?;
+    let rt = runtime(&mut cx)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    rt.spawn(async move {
+        let result = match queryer::bind_params(&sql, &params) {
+            Ok(sql) => {
+                let options = QueryOptions {
+                    timeout,
+                    ..Default::default()
+                };
+                queryer::query_with_options(sql, options).await
+            }
+            Err(e) => Err(e),
+        };
+
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(data) => to_js(&mut cx, data, format),
+            Err(err) => {
+                let error = cx.error(format!("{:#}", err))?;
+                let kind = cx.string(kind_name(ErrorKind::of(&err)));
+                error.set(&mut cx, "kind", kind)?;
+                cx.throw(error)
+            }
+        });
+    });
+
+    Ok(promise)
+}
+
+/// 第二个参数可以是输出格式的字符串（兼容以前的用法），也可以是 options 对象
+fn parse_options(cx: &mut FunctionContext) -> NeonResult<Options> {
+    let mut options = Options {
+        format: Format::Csv,
+        params: HashMap::new(),
+        timeout: None,
+    };
+    let arg = match cx.argument_opt(1) {
+        Some(v) => v,
+        None => return Ok(options),
     };
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let data = rt.block_on(async { queryer::query(sql).await.unwrap() });
 
-    match output.as_str() {
-        "csv" => Ok(cx.string(data.to_csv().unwrap())),
+    if let Ok(format) = arg.downcast::<JsString, _>(cx) {
+        options.format = parse_format(cx, &format.value(cx))?;
+    } else if let Ok(obj) = arg.downcast::<JsObject, _>(cx) {
+        if let Some(format) = obj.get_opt::<JsString, _, _>(cx, "format")? {
+            options.format = parse_format(cx, &format.value(cx))?;
+        }
+        if let Some(params) = obj.get_opt::<JsObject, _, _>(cx, "params")? {
+            options.params = parse_params(cx, params)?;
+        }
+        if let Some(timeout) = obj.get_opt::<JsNumber, _, _>(cx, "timeout")? {
+            options.timeout = Some(Duration::from_millis(timeout.value(cx) as u64));
+        }
+    } else if !arg.is_a::<JsUndefined, _>(cx) {
+        return cx.throw_type_error("options should be a string or an object");
+    }
+
+    Ok(options)
+}
+
+fn parse_format(cx: &mut FunctionContext, format: &str) -> NeonResult<Format> {
+    match format {
+        "csv" => Ok(Format::Csv),
+        "json" => Ok(Format::Json),
         v => cx.throw_type_error(format!("Output type {} not supported", v)),
     }
 }
 
+/// 把 { name: value } 转换成 SQL 参数，值只能是 number / string / boolean / null
+fn parse_params(
+    cx: &mut FunctionContext,
+    params: Handle<JsObject>,
+) -> NeonResult<HashMap<String, Param>> {
+    let keys = params.get_own_property_names(cx)?.to_vec(cx)?;
+    let mut result = HashMap::with_capacity(keys.len());
+    for key in keys {
+        let name = key.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let value: Handle<JsValue> = params.get(cx, name.as_str())?;
+        let param = if let Ok(v) = value.downcast::<JsNumber, _>(cx) {
+            Param::Number(v.value(cx))
+        } else if let Ok(v) = value.downcast::<JsString, _>(cx) {
+            Param::Text(v.value(cx))
+        } else if let Ok(v) = value.downcast::<JsBoolean, _>(cx) {
+            Param::Bool(v.value(cx))
+        } else if value.is_a::<JsNull, _>(cx) || value.is_a::<JsUndefined, _>(cx) {
+            Param::Null
+        } else {
+            return cx.throw_type_error(format!("Unsupported value for parameter {}", name));
+        };
+        result.insert(name, param);
+    }
+    Ok(result)
+}
+
+/// 把查询结果转换成 JS 的值：csv 字符串，或者对象数组
+fn to_js<'a>(cx: &mut TaskContext<'a>, data: DataSet, format: Format) -> JsResult<'a, JsValue> {
+    match format {
+        Format::Csv => match data.to_csv() {
+            Ok(v) => Ok(cx.string(v).upcast()),
+            Err(e) => cx.throw_error(e.to_string()),
+        },
+        Format::Json => {
+            let json = match data.to_json() {
+                Ok(v) => cx.string(v),
+                Err(e) => return cx.throw_error(e.to_string()),
+            };
+            let global = cx.global();
+            let json_obj: Handle<JsObject> = global.get(cx, "JSON")?;
+            let parse: Handle<JsFunction> = json_obj.get(cx, "parse")?;
+            parse.call(cx, json_obj, [json.upcast::<JsValue>()])
+        }
+    }
+}
+
+/// registerFunction(name, fn)：fn 对每一行调用一次，参数是这一行的值。
+/// 函数在 JS 的线程上执行，查询线程等待它返回
+fn register_function(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let f = Arc::new(cx.argument::<JsFunction>(1)?.root(&mut cx));
+    let mut channel = cx.channel();
+    // 注册的函数不应该让 node 进程一直不退出
+    channel.unref(&mut cx);
+
+    queryer::register_row_function(&name, move |rows| {
+        let f = f.clone();
+        let rows = rows.to_vec();
+        channel
+            .send(move |mut cx| {
+                let f = f.to_inner(&mut cx);
+                let this = cx.undefined();
+                let mut values = Vec::with_capacity(rows.len());
+                for row in rows {
+                    let args = row
+                        .iter()
+                        .map(|v| to_js_value(&mut cx, v))
+                        .collect::<NeonResult<Vec<_>>>()?;
+                    let result = match cx.try_catch(|cx| f.call(cx, this, args)) {
+                        Ok(v) => from_js_value(&mut cx, v),
+                        Err(e) => Err(e.to_string(&mut cx)?.value(&mut cx)),
+                    };
+                    values.push(result);
+                }
+                Ok(values)
+            })
+            .join()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .into_iter()
+            .map(|v| v.map_err(|e| anyhow::anyhow!(e)))
+            .collect()
+    });
+    Ok(cx.undefined())
+}
+
+/// deregisterFunction(name) => 之前是否注册过
+fn deregister_function(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    Ok(cx.boolean(queryer::deregister_function(&name)))
+}
+
+fn to_js_value<'a>(cx: &mut TaskContext<'a>, value: &Cell) -> JsResult<'a, JsValue> {
+    Ok(match value {
+        Cell::Null => cx.null().upcast(),
+        Cell::Bool(v) => cx.boolean(*v).upcast(),
+        Cell::Int(v) => cx.number(*v as f64).upcast(),
+        Cell::Float(v) => cx.number(*v).upcast(),
+        Cell::Text(v) => cx.string(v).upcast(),
+    })
+}
+
+/// 返回值只能是 number / string / boolean / null，整数的 number 当作整数
+fn from_js_value(cx: &mut TaskContext, value: Handle<JsValue>) -> Result<Cell, String> {
+    if let Ok(v) = value.downcast::<JsNumber, _>(cx) {
+        let v = v.value(cx);
+        if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+            Ok(Cell::Int(v as i64))
+        } else {
+            Ok(Cell::Float(v))
+        }
+    } else if let Ok(v) = value.downcast::<JsString, _>(cx) {
+        Ok(Cell::Text(v.value(cx)))
+    } else if let Ok(v) = value.downcast::<JsBoolean, _>(cx) {
+        Ok(Cell::Bool(v.value(cx)))
+    } else if value.is_a::<JsNull, _>(cx) || value.is_a::<JsUndefined, _>(cx) {
+        Ok(Cell::Null)
+    } else {
+        Err("Function should return a number, string, boolean or null".to_string())
+    }
+}
+
+/// 错误分类的名字，JS 里可以通过 err.kind 判断
+fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Syntax => "syntax",
+        ErrorKind::Fetch => "fetch",
+        ErrorKind::Data => "data",
+        ErrorKind::Limit => "limit",
+        _ => "other",
+    }
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("example_sql", example_sql)?;
     cx.export_function("query", query)?;
+    cx.export_function("registerFunction", register_function)?;
+    cx.export_function("deregisterFunction", deregister_function)?;
     Ok(())
 }
@@ -1,17 +1,104 @@
 #![allow(clippy::needless_option_as_deref)]
-use pyo3::{exceptions, prelude::*};
+use anyhow::anyhow;
+use polars::export::arrow::{array::Array, ffi};
+use pyo3::types::{PyBool, PyFloat, PyLong, PyString, PyTuple};
+use pyo3::{create_exception, exceptions, ffi::Py_uintptr_t, prelude::*};
+use queryer::{Cell, DataSet, ErrorKind};
+
+// 所有 queryer 的错误都继承自 QueryerError，Python 里可以按需捕获
+create_exception!(queryer_py, QueryerError, exceptions::PyException);
+create_exception!(queryer_py, SqlSyntaxError, QueryerError);
+create_exception!(queryer_py, FetchError, QueryerError);
+create_exception!(queryer_py, DataError, QueryerError);
+create_exception!(queryer_py, LimitError, QueryerError);
 
 #[pyfunction]
 pub fn example_sql() -> PyResult<String> {
     Ok(queryer::example_sql())
 }
 
+/// 同步查询，所有调用共享同一个 tokio 运行时，查询期间释放 GIL
+#[pyfunction]
+pub fn query(py: Python, sql: &str, output: Option<&str>) -> PyResult<PyObject> {
+    let rt = pyo3_asyncio::tokio::get_runtime();
+    let data = py
+        .allow_threads(|| rt.block_on(queryer::query(sql)))
+        .map_err(to_py_err)?;
+    convert(py, data, output)
+}
+
+/// 异步查询，返回一个可以在 asyncio 中 await 的对象
+#[pyfunction]
+pub fn query_async(py: Python, sql: String, output: Option<String>) -> PyResult<&PyAny> {
+    pyo3_asyncio::tokio::future_into_py(py, async move {
+        let data = queryer::query(sql).await.map_err(to_py_err)?;
+        Python::with_gil(|py| convert(py, data, output.as_deref()))
+    })
+}
+
+/// 注册一个可以在 SQL 中调用的函数，f 对每一行调用一次，参数是这一行的值
 #[pyfunction]
-pub fn query(sql: &str, output: Option<&str>) -> PyResult<String> {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let data = rt.block_on(async { queryer::query(sql).await.unwrap() });
+pub fn register_function(py: Python, name: &str, f: PyObject) -> PyResult<()> {
+    if !f.as_ref(py).is_callable() {
+        return Err(exceptions::PyTypeError::new_err(format!(
+            "{} should be callable",
+            name
+        )));
+    }
+    queryer::register_row_function(name, move |rows| {
+        // 一批行只获取一次 GIL
+        Python::with_gil(|py| {
+            rows.iter()
+                .map(|row| {
+                    let args = PyTuple::new(py, row.iter().map(|v| to_py(py, v)));
+                    let result = f.call1(py, args).map_err(|e| anyhow!("{}", e))?;
+                    from_py(result.as_ref(py))
+                })
+                .collect()
+        })
+    });
+    Ok(())
+}
+
+/// 取消注册，返回之前是否注册过
+#[pyfunction]
+pub fn deregister_function(name: &str) -> bool {
+    queryer::deregister_function(name)
+}
+
+fn to_py(py: Python, value: &Cell) -> PyObject {
+    match value {
+        Cell::Null => py.None(),
+        Cell::Bool(v) => v.into_py(py),
+        Cell::Int(v) => v.into_py(py),
+        Cell::Float(v) => v.into_py(py),
+        Cell::Text(v) => v.into_py(py),
+    }
+}
+
+/// 函数的返回值只能是 None / bool / int / float / str
+fn from_py(value: &PyAny) -> anyhow::Result<Cell> {
+    if value.is_none() {
+        Ok(Cell::Null)
+    } else if let Ok(v) = value.downcast::<PyBool>() {
+        Ok(Cell::Bool(v.is_true()))
+    } else if let Ok(v) = value.downcast::<PyLong>() {
+        Ok(Cell::Int(v.extract()?))
+    } else if let Ok(v) = value.downcast::<PyFloat>() {
+        Ok(Cell::Float(v.value()))
+    } else if let Ok(v) = value.downcast::<PyString>() {
+        Ok(Cell::Text(v.to_str()?.to_owned()))
+    } else {
+        Err(anyhow!("Unsupported return value {}", value))
+    }
+}
+
+/// 把查询结果转换成需要的输出格式
+fn convert(py: Python, data: DataSet, output: Option<&str>) -> PyResult<PyObject> {
     match output {
-        Some("csv") | None => Ok(data.to_csv().unwrap()),
+        Some("csv") | None => Ok(data.to_csv().map_err(to_py_err)?.into_py(py)),
+        Some("arrow") => to_pyarrow(py, &data),
+        Some("pandas") => to_pyarrow(py, &data)?.call_method0(py, "to_pandas"),
         Some(v) => Err(exceptions::PyTypeError::new_err(format!(
             "Output type {} not supported",
             v
@@ -19,10 +106,55 @@ pub fn query(sql: &str, output: Option<&str>) -> PyResult<String> {
     }
 }
 
+/// 通过 Arrow C data interface 把 DataSet 的每一列零拷贝地交给 pyarrow，组成 pyarrow.Table
+fn to_pyarrow(py: Python, data: &DataSet) -> PyResult<PyObject> {
+    let pyarrow = py.import("pyarrow")?;
+    let mut arrays = Vec::with_capacity(data.width());
+    let mut names = Vec::with_capacity(data.width());
+    for s in data.get_columns() {
+        // 多个 chunk 先合并成一个，这样每列只需要导出一个 array
+        let s = s.rechunk();
+        let array = ffi::ArrowArray::try_from(s.chunks()[0].data().clone())
+            .map_err(|e| DataError::new_err(e.to_string()))?;
+        let (array_ptr, schema_ptr) = unsafe { ffi::ArrowArray::into_raw(array) };
+        let array = pyarrow.getattr("Array")?.call_method1(
+            "_import_from_c",
+            (array_ptr as Py_uintptr_t, schema_ptr as Py_uintptr_t),
+        )?;
+        arrays.push(array);
+        names.push(s.name().to_owned());
+    }
+
+    let table = pyarrow
+        .getattr("Table")?
+        .call_method1("from_arrays", (arrays, names))?;
+    Ok(table.into())
+}
+
+/// 把 queryer 的错误映射成对应的 Python 异常
+fn to_py_err(err: anyhow::Error) -> PyErr {
+    let msg = format!("{:#}", err);
+    match ErrorKind::of(&err) {
+        ErrorKind::Syntax => SqlSyntaxError::new_err(msg),
+        ErrorKind::Fetch => FetchError::new_err(msg),
+        ErrorKind::Data => DataError::new_err(msg),
+        ErrorKind::Limit => LimitError::new_err(msg),
+        _ => QueryerError::new_err(msg),
+    }
+}
+
 #[pymodule]
-fn queryer_py(_py: Python, m: &PyModule) -> PyResult<()> {
+fn queryer_py(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(query, m)?)?;
+    m.add_function(wrap_pyfunction!(query_async, m)?)?;
     m.add_function(wrap_pyfunction!(example_sql, m)?)?;
+    m.add_function(wrap_pyfunction!(register_function, m)?)?;
+    m.add_function(wrap_pyfunction!(deregister_function, m)?)?;
+    m.add("QueryerError", py.get_type::<QueryerError>())?;
+    m.add("SqlSyntaxError", py.get_type::<SqlSyntaxError>())?;
+    m.add("FetchError", py.get_type::<FetchError>())?;
+    m.add("DataError", py.get_type::<DataError>())?;
+    m.add("LimitError", py.get_type::<LimitError>())?;
     Ok(())
 }
 
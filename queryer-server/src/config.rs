@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+use queryer::QueryOptions;
+use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:3000";
+
+/// 从环境变量读取监听地址和服务级别的资源限制：
+/// - QUERYER_ADDR：监听地址，默认 127.0.0.1:3000
+/// - QUERYER_MAX_SOURCE_BYTES / QUERYER_MAX_ROWS / QUERYER_TIMEOUT_MS
+/// - QUERYER_ALLOWED_SCHEMES / QUERYER_ALLOWED_HOSTS：逗号分隔
+pub fn from_env() -> Result<(SocketAddr, QueryOptions)> {
+    let addr = env::var("QUERYER_ADDR")
+        .unwrap_or_else(|_| DEFAULT_ADDR.to_owned())
+        .parse()?;
+    let options = QueryOptions {
+        max_source_bytes: parse("QUERYER_MAX_SOURCE_BYTES")?,
+        max_rows: parse("QUERYER_MAX_ROWS")?,
+        timeout: parse("QUERYER_TIMEOUT_MS")?.map(Duration::from_millis),
+        allowed_schemes: list("QUERYER_ALLOWED_SCHEMES"),
+        allowed_hosts: list("QUERYER_ALLOWED_HOSTS"),
+    };
+    Ok((addr, options))
+}
+
+/// Postgres 协议的监听地址，来自 QUERYER_PG_ADDR，没有设置时不启动
+pub fn pg_addr() -> Result<Option<SocketAddr>> {
+    parse("QUERYER_PG_ADDR")
+}
+
+fn parse<T: FromStr>(name: &str) -> Result<Option<T>> {
+    match env::var(name) {
+        Ok(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("Invalid value {} for {}", v, name)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn list(name: &str) -> Option<Vec<String>> {
+    env::var(name).ok().map(|v| {
+        v.split(',')
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+            .collect()
+    })
+}
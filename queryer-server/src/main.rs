@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+mod config;
+mod metrics;
+mod pgwire;
+mod routes;
+
+use routes::{app, AppState};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // 初始化 tracing
+    tracing_subscriber::fmt::init();
+
+    // 服务的地址和默认的资源限制都从环境变量读取，每个请求只能在此基础上收紧限制
+    let (addr, options) = config::from_env()?;
+    let state = Arc::new(AppState::new(options));
+
+    // BI 工具可以通过 Postgres 协议连接，和 HTTP 接口共享资源限制和统计信息
+    if let Some(pg_addr) = config::pg_addr()? {
+        let listener = TcpListener::bind(pg_addr).await?;
+        info!("postgres protocol listening on {}", pg_addr);
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pgwire::serve(listener, state).await {
+                warn!("postgres protocol server stopped: {:#}", e);
+            }
+        });
+    }
+
+    info!("listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(app(state).into_make_service())
+        .await?;
+    Ok(())
+}
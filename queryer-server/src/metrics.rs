@@ -0,0 +1,70 @@
+use queryer::ErrorKind;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 错误的分类，和 ErrorKind 对应
+const KINDS: [&str; 5] = ["syntax", "fetch", "data", "limit", "other"];
+
+/// 查询的统计信息，以 Prometheus 的文本格式输出
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queries: AtomicU64,
+    rows: AtomicU64,
+    duration_us: AtomicU64,
+    errors: [AtomicU64; KINDS.len()],
+}
+
+impl Metrics {
+    /// 记录一次成功的查询
+    pub fn record_ok(&self, elapsed: Duration, rows: usize) {
+        self.record(elapsed);
+        self.rows.fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// 记录一次失败的查询
+    pub fn record_err(&self, elapsed: Duration, kind: ErrorKind) {
+        self.record(elapsed);
+        let i = KINDS.iter().position(|v| *v == kind_name(kind)).unwrap();
+        self.errors[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record(&self, elapsed: Duration) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        self.duration_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        let queries = self.queries.load(Ordering::Relaxed);
+        let rows = self.rows.load(Ordering::Relaxed);
+        let seconds = self.duration_us.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(s, "# TYPE queryer_queries_total counter");
+        let _ = writeln!(s, "queryer_queries_total {}", queries);
+        let _ = writeln!(s, "# TYPE queryer_rows_total counter");
+        let _ = writeln!(s, "queryer_rows_total {}", rows);
+        let _ = writeln!(s, "# TYPE queryer_query_duration_seconds_sum counter");
+        let _ = writeln!(s, "queryer_query_duration_seconds_sum {}", seconds);
+        let _ = writeln!(s, "# TYPE queryer_query_errors_total counter");
+        for (kind, count) in KINDS.iter().zip(&self.errors) {
+            let _ = writeln!(
+                s,
+                "queryer_query_errors_total{{kind=\"{}\"}} {}",
+                kind,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        s
+    }
+}
+
+pub fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Syntax => "syntax",
+        ErrorKind::Fetch => "fetch",
+        ErrorKind::Data => "data",
+        ErrorKind::Limit => "limit",
+        _ => "other",
+    }
+}
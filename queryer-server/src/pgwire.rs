@@ -0,0 +1,333 @@
+use crate::routes::AppState;
+use anyhow::{anyhow, bail, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use polars::prelude::{DataType, Series};
+use queryer::{DataSet, ErrorKind, LimitError, Session};
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// 协议版本 3.0
+const PROTOCOL_VERSION: i32 = 196608;
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// 单条消息最大的长度，避免恶意的客户端让我们分配过多的内存
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// 在 listener 上接受 Postgres 协议的连接。只支持 simple query，
+/// 每条 Query 消息交给 queryer 执行，结果以文本格式返回
+pub async fn serve(listener: TcpListener, state: Arc<AppState>) -> Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            info!("postgres connection from {}", addr);
+            if let Err(e) = handle(stream, &state).await {
+                warn!("postgres connection {} closed: {:#}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &AppState) -> Result<()> {
+    if !startup(&mut stream).await? {
+        return Ok(());
+    }
+
+    let session = Session::default().with_options(state.options.clone());
+    // extended query 出错之后，要忽略后续的消息直到 Sync
+    let mut skip_until_sync = false;
+    loop {
+        let tag = match stream.read_u8().await {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let len = stream.read_i32().await?;
+        let body = read_body(&mut stream, len).await?;
+        match tag {
+            b'Q' => {
+                let sql = std::str::from_utf8(body.strip_suffix(&[0]).unwrap_or(&body))?;
+                let mut out = simple_query(&session, state, sql).await;
+                out.extend(ready_for_query());
+                stream.write_all(&out).await?;
+            }
+            b'S' => {
+                skip_until_sync = false;
+                stream.write_all(&ready_for_query()).await?;
+            }
+            b'P' | b'B' | b'D' | b'E' | b'C' if !skip_until_sync => {
+                skip_until_sync = true;
+                let err = anyhow!("Extended query protocol is not supported, use simple query");
+                stream.write_all(&error_response(&err)).await?;
+            }
+            b'X' => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// 处理启动阶段：拒绝 SSL，接受任何用户，不需要密码。返回 false 表示连接已经结束
+async fn startup(stream: &mut TcpStream) -> Result<bool> {
+    loop {
+        let len = stream.read_i32().await?;
+        let body = read_body(stream, len).await?;
+        if body.len() < 4 {
+            bail!("Invalid startup message");
+        }
+        match i32::from_be_bytes([body[0], body[1], body[2], body[3]]) {
+            SSL_REQUEST | GSSENC_REQUEST => stream.write_all(b"N").await?,
+            CANCEL_REQUEST => return Ok(false),
+            PROTOCOL_VERSION => break,
+            v => bail!("Unsupported protocol version {}", v),
+        }
+    }
+
+    // AuthenticationOk
+    let mut out = message(b'R', &0i32.to_be_bytes());
+    for (name, value) in [
+        ("server_version", "14.0"),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+    ] {
+        out.extend(message(b'S', &[cstring(name), cstring(value)].concat()));
+    }
+    out.extend(ready_for_query());
+    stream.write_all(&out).await?;
+    Ok(true)
+}
+
+async fn read_body(stream: &mut TcpStream, len: i32) -> Result<Vec<u8>> {
+    let len = usize::try_from(len)
+        .ok()
+        .and_then(|v| v.checked_sub(4))
+        .filter(|v| *v <= MAX_MESSAGE_LEN)
+        .ok_or_else(|| anyhow!("Invalid message length {}", len))?;
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+async fn simple_query(session: &Session, state: &AppState, sql: &str) -> Vec<u8> {
+    if sql.trim().trim_end_matches(';').trim().is_empty() {
+        // EmptyQueryResponse
+        return message(b'I', &[]);
+    }
+
+    let start = Instant::now();
+    let result = session.query(sql).await.and_then(|ds| {
+        let out = data_rows(&ds)?;
+        Ok((ds.height(), out))
+    });
+    match result {
+        Ok((rows, out)) => {
+            state.metrics.record_ok(start.elapsed(), rows);
+            out
+        }
+        Err(err) => {
+            state
+                .metrics
+                .record_err(start.elapsed(), ErrorKind::of(&err));
+            error_response(&err)
+        }
+    }
+}
+
+/// 生成 RowDescription、每一行的 DataRow 和 CommandComplete
+fn data_rows(ds: &DataSet) -> Result<Vec<u8>> {
+    let columns = ds.get_columns();
+    let mut desc = (columns.len() as i16).to_be_bytes().to_vec();
+    for s in columns {
+        let (oid, size) = pg_type(s.dtype());
+        desc.extend(cstring(s.name()));
+        // 不属于任何表，也没有列号
+        desc.extend(0i32.to_be_bytes());
+        desc.extend(0i16.to_be_bytes());
+        desc.extend(oid.to_be_bytes());
+        desc.extend(size.to_be_bytes());
+        // 没有类型修饰，文本格式
+        desc.extend((-1i32).to_be_bytes());
+        desc.extend(0i16.to_be_bytes());
+    }
+    let mut out = message(b'T', &desc);
+
+    let values = columns.iter().map(to_text).collect::<Result<Vec<_>>>()?;
+    for i in 0..ds.height() {
+        let mut row = (columns.len() as i16).to_be_bytes().to_vec();
+        for column in &values {
+            match &column[i] {
+                Some(v) => {
+                    row.extend((v.len() as i32).to_be_bytes());
+                    row.extend(v.as_bytes());
+                }
+                None => row.extend((-1i32).to_be_bytes()),
+            }
+        }
+        out.extend(message(b'D', &row));
+    }
+
+    let tag = format!("SELECT {}", ds.height());
+    out.extend(message(b'C', &cstring(&tag)));
+    Ok(out)
+}
+
+/// polars 的类型对应的 Postgres 类型的 oid 和长度，不认识的类型都当作 text
+fn pg_type(dtype: &DataType) -> (i32, i16) {
+    match dtype {
+        DataType::Boolean => (16, 1),
+        DataType::Int32 => (23, 4),
+        DataType::Int64 | DataType::UInt32 => (20, 8),
+        // int8 放不下，用 numeric
+        DataType::UInt64 => (1700, -1),
+        DataType::Float32 => (700, 4),
+        DataType::Float64 => (701, 8),
+        DataType::Date32 => (1082, 4),
+        DataType::Date64 => (1114, 8),
+        _ => (25, -1),
+    }
+}
+
+/// 把一列转换成 Postgres 的文本格式，空值是 None
+fn to_text(s: &Series) -> Result<Vec<Option<String>>> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let values = match s.dtype() {
+        DataType::Boolean => s
+            .bool()?
+            .into_iter()
+            .map(|v| v.map(|v| if v { "t" } else { "f" }.to_owned()))
+            .collect(),
+        DataType::Date32 => s
+            .cast_with_dtype(&DataType::Int32)?
+            .i32()?
+            .into_iter()
+            .map(|v| v.map(|v| (epoch + Duration::days(v as i64)).to_string()))
+            .collect(),
+        DataType::Date64 => s
+            .cast_with_dtype(&DataType::Int64)?
+            .i64()?
+            .into_iter()
+            .map(|v| {
+                v.and_then(NaiveDateTime::from_timestamp_millis)
+                    .map(|v| v.to_string())
+            })
+            .collect(),
+        _ => s
+            .cast_with_dtype(&DataType::Utf8)?
+            .utf8()?
+            .into_iter()
+            .map(|v| v.map(|v| v.to_owned()))
+            .collect(),
+    };
+    Ok(values)
+}
+
+/// ErrorResponse，SQLSTATE 由错误的分类决定
+fn error_response(err: &anyhow::Error) -> Vec<u8> {
+    let code = match (ErrorKind::of(err), err.downcast_ref::<LimitError>()) {
+        (_, Some(LimitError::Timeout(_))) => "57014",
+        (_, Some(LimitError::NotAllowed(_))) => "42501",
+        (ErrorKind::Syntax, _) => "42601",
+        (ErrorKind::Fetch, _) => "58030",
+        (ErrorKind::Data, _) => "22000",
+        (ErrorKind::Limit, _) => "54000",
+        _ => "0A000",
+    };
+    let msg = format!("{:#}", err);
+    let mut body = Vec::new();
+    for (field, value) in [(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', &msg)] {
+        body.push(field);
+        body.extend(cstring(value));
+    }
+    body.push(0);
+    message(b'E', &body)
+}
+
+/// ReadyForQuery，我们没有事务，状态总是空闲
+fn ready_for_query() -> Vec<u8> {
+    message(b'Z', b"I")
+}
+
+fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 5);
+    out.push(tag);
+    out.extend((body.len() as i32 + 4).to_be_bytes());
+    out.extend(body);
+    out
+}
+
+fn cstring(s: &str) -> Vec<u8> {
+    let mut v = s.as_bytes().to_vec();
+    v.push(0);
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::NamedFrom;
+    use queryer::testing::temp_source;
+    use tokio_postgres::error::SqlState;
+    use tokio_postgres::{NoTls, SimpleQueryMessage};
+
+    #[tokio::test]
+    async fn simple_query_should_work() {
+        let source = temp_source("pgwire.csv", "name,age,cute\ntom,10,true\njerry,3,false\n");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(listener, Arc::new(AppState::default())));
+
+        let config = format!("host=127.0.0.1 port={} user=test", port);
+        let (client, connection) = tokio_postgres::connect(&config, NoTls).await.unwrap();
+        tokio::spawn(connection);
+
+        let sql = format!("SELECT name, age, cute FROM {} WHERE age > 5", source);
+        let rows: Vec<_> = client
+            .simple_query(&sql)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|v| match v {
+                SimpleQueryMessage::Row(row) => Some(row),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("name"), Some("tom"));
+        assert_eq!(rows[0].get("age"), Some("10"));
+        assert_eq!(rows[0].get("cute"), Some("t"));
+
+        let err = client.simple_query("SELECT FROM WHERE").await.unwrap_err();
+        assert_eq!(err.code(), Some(&SqlState::SYNTAX_ERROR));
+    }
+
+    #[test]
+    fn pg_type_should_work() {
+        assert_eq!(pg_type(&DataType::Int64), (20, 8));
+        assert_eq!(pg_type(&DataType::Utf8), (25, -1));
+        assert_eq!(pg_type(&DataType::Date32), (1082, 4));
+        assert_eq!(pg_type(&DataType::Date64), (1114, 8));
+    }
+
+    #[test]
+    fn to_text_should_format_dates() {
+        let s = Series::new("d", &[Some(18840i32), None])
+            .cast_with_dtype(&DataType::Date32)
+            .unwrap();
+        assert_eq!(to_text(&s).unwrap(), [Some("2021-08-01".to_owned()), None]);
+        let s = Series::new("t", &[1627812000000i64])
+            .cast_with_dtype(&DataType::Date64)
+            .unwrap();
+        assert_eq!(
+            to_text(&s).unwrap(),
+            [Some("2021-08-01 10:00:00".to_owned())]
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use crate::metrics::{kind_name, Metrics};
+use anyhow::anyhow;
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Extension, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use queryer::{
+    bind_params, ColumnInfo, ErrorKind, LimitError, OutputFormat, Param, QueryOptions, Session,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tower_http::trace::TraceLayer;
+use tracing::info;
+
+/// 所有请求共享的状态
+#[derive(Debug, Default)]
+pub struct AppState {
+    /// 服务级别的资源限制
+    pub(crate) options: QueryOptions,
+    pub(crate) metrics: Metrics,
+}
+
+impl AppState {
+    pub fn new(options: QueryOptions) -> Self {
+        Self {
+            options,
+            metrics: Metrics::default(),
+        }
+    }
+}
+
+/// POST /query 的请求
+#[derive(Debug, Deserialize)]
+struct QueryRequest {
+    sql: String,
+    /// SQL 中 ${name} 的值
+    #[serde(default)]
+    params: HashMap<String, Value>,
+    /// csv（默认）、json、arrow 或者 parquet
+    format: Option<String>,
+    #[serde(default)]
+    options: RequestOptions,
+}
+
+/// 每个请求自己的资源限制，只能比服务级别的限制更严格
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestOptions {
+    max_source_bytes: Option<u64>,
+    max_rows: Option<usize>,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaParams {
+    source: String,
+}
+
+/// GET /schema 返回的一列
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaColumn {
+    name: String,
+    dtype: String,
+    null_count: usize,
+    samples: Vec<String>,
+}
+
+impl From<ColumnInfo> for SchemaColumn {
+    fn from(info: ColumnInfo) -> Self {
+        Self {
+            name: info.name,
+            dtype: info.dtype,
+            null_count: info.null_count,
+            samples: info.samples,
+        }
+    }
+}
+
+/// 出错时返回 `{ "error": "...", "kind": "syntax" }`，HTTP 状态码由错误的分类决定
+struct ApiError(anyhow::Error);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let kind = ErrorKind::of(&self.0);
+        let status = match (kind, self.0.downcast_ref::<LimitError>()) {
+            (_, Some(LimitError::Timeout(_))) => StatusCode::REQUEST_TIMEOUT,
+            (_, Some(LimitError::NotAllowed(_))) => StatusCode::FORBIDDEN,
+            (ErrorKind::Limit, _) => StatusCode::PAYLOAD_TOO_LARGE,
+            (ErrorKind::Fetch, _) => StatusCode::BAD_GATEWAY,
+            (ErrorKind::Data, _) => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = json!({ "error": format!("{:#}", self.0), "kind": kind_name(kind) });
+        (status, Json(body)).into_response()
+    }
+}
+
+pub fn app(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/query", post(query))
+        .route("/schema", get(schema))
+        .route("/health", get(health))
+        .route("/metrics", get(metrics))
+        .layer(TraceLayer::new_for_http())
+        .layer(Extension(state))
+}
+
+async fn query(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(req): Json<QueryRequest>,
+) -> Result<Response, ApiError> {
+    let start = Instant::now();
+    let result = run_query(&state, req).await;
+    let (format, data) = match result {
+        Ok(v) => {
+            state.metrics.record_ok(start.elapsed(), v.1.height());
+            v
+        }
+        Err(err) => {
+            state
+                .metrics
+                .record_err(start.elapsed(), ErrorKind::of(&err));
+            return Err(err.into());
+        }
+    };
+    info!("query finished in {:?}", start.elapsed());
+
+    // 序列化是同步的，放到专门的线程池里，一边写一边发给客户端
+    let (tx, rx) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter(tx.clone()));
+        let result = data
+            .write(&mut writer, format)
+            .and_then(|_| Ok(writer.flush()?));
+        if let Err(e) = result {
+            // 客户端已经断开时发送失败，不需要处理
+            let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+    let stream =
+        futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|v| (v, rx)) });
+    let body = StreamBody::new(stream);
+    Ok(([(header::CONTENT_TYPE, format.content_type())], body).into_response())
+}
+
+/// 每次发给客户端的数据大小
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// 把写入的数据通过 channel 发给响应的 body。客户端断开之后写入失败，序列化随之停止
+struct ChannelWriter(mpsc::Sender<io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+async fn run_query(
+    state: &AppState,
+    req: QueryRequest,
+) -> anyhow::Result<(OutputFormat, queryer::DataSet)> {
+    let format = match &req.format {
+        Some(v) => v.parse()?,
+        None => OutputFormat::Csv,
+    };
+    let params = req
+        .params
+        .into_iter()
+        .map(|(k, v)| Ok((k, to_param(v)?)))
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    let sql = bind_params(&req.sql, &params)?;
+
+    let options = merge_options(&state.options, &req.options);
+    let data = Session::default().with_options(options).query(sql).await?;
+    Ok((format, data))
+}
+
+async fn schema(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<SchemaParams>,
+) -> Result<Json<Vec<SchemaColumn>>, ApiError> {
+    let session = Session::default().with_options(state.options.clone());
+    let columns = session.schema(&params.source).await?;
+    Ok(Json(columns.into_iter().map(SchemaColumn::from).collect()))
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn metrics(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    let content_type = "text/plain; version=0.0.4";
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        state.metrics.render(),
+    )
+}
+
+/// JSON 的值转换成 SQL 参数，只支持 number / string / boolean / null。
+/// 整数保持为整数，避免超过 2^53 的 id 之类的值丢失精度
+fn to_param(value: Value) -> anyhow::Result<Param> {
+    match value {
+        Value::Null => Ok(Param::Null),
+        Value::Bool(v) => Ok(Param::Bool(v)),
+        Value::Number(v) if v.is_u64() || v.is_i64() => v
+            .as_i64()
+            .map(Param::Integer)
+            .ok_or_else(|| anyhow!("Number {} is out of range", v)),
+        Value::Number(v) => v
+            .as_f64()
+            .map(Param::Number)
+            .ok_or_else(|| anyhow!("Invalid number {}", v)),
+        Value::String(v) => Ok(Param::Text(v)),
+        v => Err(anyhow!(
+            "Param {} should be a number, string, boolean or null",
+            v
+        )),
+    }
+}
+
+/// 每一项都取服务和请求中更严格的那个，allowlist 只由服务决定
+fn merge_options(base: &QueryOptions, req: &RequestOptions) -> QueryOptions {
+    fn min<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+    QueryOptions {
+        max_source_bytes: min(base.max_source_bytes, req.max_source_bytes),
+        max_rows: min(base.max_rows, req.max_rows),
+        timeout: min(base.timeout, req.timeout_ms.map(Duration::from_millis)),
+        ..base.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use queryer::testing::temp_source;
+    use tower::ServiceExt;
+
+    fn fixture() -> String {
+        temp_source("server.csv", "name,age\ntom,10\njerry,3\nspike,8\n")
+    }
+
+    async fn send(app: Router, req: Request<Body>) -> (StatusCode, String) {
+        let res = app.oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn post_query(body: Value) -> Request<Body> {
+        Request::post("/query")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn query_should_work() {
+        let state = Arc::new(AppState::default());
+        let body = json!({
+            "sql": format!("SELECT name FROM {} WHERE age > ${{age}}", fixture()),
+            "params": { "age": 5 },
+        });
+        let (status, body) = send(app(state.clone()), post_query(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "name\ntom\nspike\n");
+
+        let body = json!({ "sql": "SELECT FROM WHERE" });
+        let (status, body) = send(app(state.clone()), post_query(body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("\"kind\":\"syntax\""));
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let (_, body) = send(app(state), req).await;
+        assert!(body.contains("queryer_queries_total 2"));
+        assert!(body.contains("queryer_query_errors_total{kind=\"syntax\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn request_options_should_be_enforced() {
+        let state = Arc::new(AppState::new(QueryOptions {
+            max_rows: Some(10),
+            ..Default::default()
+        }));
+        let body = json!({
+            "sql": format!("SELECT name FROM {}", fixture()),
+            "format": "json",
+            "options": { "maxRows": 2 },
+        });
+        let (status, body) = send(app(state), post_query(body)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body.contains("\"kind\":\"limit\""));
+    }
+
+    #[test]
+    fn to_param_should_keep_integers() {
+        let v = to_param(json!(9007199254740993i64)).unwrap();
+        assert_eq!(v.to_string(), "9007199254740993");
+        assert_eq!(to_param(json!(-5)).unwrap().to_string(), "(-5)");
+        assert_eq!(to_param(json!(1.5)).unwrap().to_string(), "1.5");
+        assert!(to_param(json!(u64::MAX)).is_err());
+    }
+
+    #[tokio::test]
+    async fn schema_and_health_should_work() {
+        let state = Arc::new(AppState::default());
+        let uri = format!("/schema?source={}", fixture());
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let (status, body) = send(app(state.clone()), req).await;
+        assert_eq!(status, StatusCode::OK);
+        let columns: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(columns[0]["name"], "name");
+        assert_eq!(columns[1]["nullCount"], 0);
+
+        let req = Request::get("/health").body(Body::empty()).unwrap();
+        let (status, _) = send(app(state), req).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
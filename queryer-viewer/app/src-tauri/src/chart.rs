@@ -0,0 +1,74 @@
+use queryer::{Aggregation, Axis, Chart, ChartSpec, DataSet, TimeBucket};
+use serde::{Deserialize, Serialize};
+
+/// 前端传过来的图表描述
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartRequest {
+  pub x: String,
+  #[serde(default)]
+  pub y: Vec<String>,
+  /// count / sum / mean / min / max，默认 count
+  pub aggregation: Option<String>,
+  pub bin_size: Option<f64>,
+  /// day / week / month / quarter / year
+  pub time_bucket: Option<String>,
+}
+
+/// 返回给前端的图表数据，x 和每个 series 的 values 一一对应
+#[derive(Debug, Serialize)]
+pub struct ChartData {
+  pub x: ChartAxis,
+  pub series: Vec<ChartLine>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ChartAxis {
+  Numbers(Vec<Option<f64>>),
+  Labels(Vec<Option<String>>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartLine {
+  pub name: String,
+  pub values: Vec<Option<f64>>,
+}
+
+impl ChartRequest {
+  pub fn to_spec(self) -> anyhow::Result<ChartSpec> {
+    let aggregation: Aggregation = match self.aggregation {
+      Some(v) => v.parse()?,
+      None => Aggregation::Count,
+    };
+    let time_bucket: Option<TimeBucket> = match self.time_bucket {
+      Some(v) => Some(v.parse()?),
+      None => None,
+    };
+    Ok(ChartSpec {
+      y: self.y,
+      aggregation,
+      bin_size: self.bin_size,
+      time_bucket,
+      ..ChartSpec::new(self.x)
+    })
+  }
+}
+
+impl ChartData {
+  pub fn new(data: &DataSet, request: ChartRequest) -> anyhow::Result<Self> {
+    let Chart { x, series } = data.chart(&request.to_spec()?)?;
+    let x = match x {
+      Axis::Numbers(v) => ChartAxis::Numbers(v),
+      Axis::Labels(v) => ChartAxis::Labels(v),
+    };
+    let series = series
+      .into_iter()
+      .map(|s| ChartLine {
+        name: s.name,
+        values: s.values,
+      })
+      .collect();
+    Ok(Self { x, series })
+  }
+}
@@ -0,0 +1,31 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 从配置目录读取 json 文件，文件不存在或者格式不对时返回默认值
+pub fn load_json<T: DeserializeOwned + Default>(path: Option<&Path>) -> T {
+  path
+    .and_then(|p| fs::read_to_string(p).ok())
+    .and_then(|content| serde_json::from_str(&content).ok())
+    .unwrap_or_default()
+}
+
+/// 把数据写入配置目录下的 json 文件
+pub fn save_json<T: Serialize + ?Sized>(path: Option<&Path>, value: &T) -> anyhow::Result<()> {
+  if let Some(path) = path {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(value)?)?;
+  }
+  Ok(())
+}
+
+/// 当前的 unix 时间戳，单位秒
+pub fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or_default()
+}
@@ -0,0 +1,66 @@
+use queryer::{Catalog, CompletionKind, DiagnosticKind, Span};
+use serde::Serialize;
+
+/// 前端使用字符偏移，queryer 使用字节偏移，这里做转换
+#[derive(Debug, Serialize)]
+pub struct CharSpan {
+  pub start: usize,
+  pub end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SqlDiagnostic {
+  /// syntax / unsupported
+  pub kind: &'static str,
+  pub message: String,
+  pub span: CharSpan,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SqlCompletion {
+  pub label: String,
+  /// keyword / table / column
+  pub kind: &'static str,
+  pub replace: CharSpan,
+}
+
+pub fn validate(sql: &str) -> Vec<SqlDiagnostic> {
+  queryer::validate(sql)
+    .into_iter()
+    .map(|d| SqlDiagnostic {
+      kind: match d.kind {
+        DiagnosticKind::Syntax => "syntax",
+        DiagnosticKind::Unsupported => "unsupported",
+      },
+      message: d.message,
+      span: to_char_span(sql, d.span),
+    })
+    .collect()
+}
+
+pub fn complete(sql: &str, cursor: usize, catalog: &Catalog) -> Vec<SqlCompletion> {
+  let cursor = sql
+    .char_indices()
+    .nth(cursor)
+    .map(|(i, _)| i)
+    .unwrap_or(sql.len());
+  queryer::complete(sql, cursor, catalog)
+    .into_iter()
+    .map(|c| SqlCompletion {
+      label: c.label,
+      kind: match c.kind {
+        CompletionKind::Keyword => "keyword",
+        CompletionKind::Table => "table",
+        CompletionKind::Column => "column",
+      },
+      replace: to_char_span(sql, c.replace),
+    })
+    .collect()
+}
+
+fn to_char_span(sql: &str, span: Span) -> CharSpan {
+  CharSpan {
+    start: sql[..span.start].chars().count(),
+    end: sql[..span.end].chars().count(),
+  }
+}
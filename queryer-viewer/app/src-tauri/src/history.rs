@@ -0,0 +1,103 @@
+use crate::config::{load_json, now, save_json};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// 最多保留多少条查询历史
+const MAX_HISTORY: usize = 500;
+const HISTORY_FILE: &str = "query_history.json";
+const SAVED_FILE: &str = "saved_queries.json";
+
+/// 一次查询的记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+  pub sql: String,
+  pub started_at: u64,
+  pub duration_ms: u64,
+  /// 成功时返回的行数
+  pub rows: Option<usize>,
+  /// 失败时的错误信息
+  pub error: Option<String>,
+}
+
+/// 用户保存的命名查询
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedQuery {
+  pub name: String,
+  pub sql: String,
+  pub saved_at: u64,
+}
+
+/// 查询历史和保存的查询，都持久化在应用的配置目录下
+pub struct History {
+  entries: Mutex<Vec<HistoryEntry>>,
+  saved: Mutex<Vec<SavedQuery>>,
+  history_path: Option<PathBuf>,
+  saved_path: Option<PathBuf>,
+}
+
+impl History {
+  pub fn load(config_dir: Option<PathBuf>) -> Self {
+    let history_path = config_dir.as_ref().map(|dir| dir.join(HISTORY_FILE));
+    let saved_path = config_dir.as_ref().map(|dir| dir.join(SAVED_FILE));
+    Self {
+      entries: Mutex::new(load_json(history_path.as_deref())),
+      saved: Mutex::new(load_json(saved_path.as_deref())),
+      history_path,
+      saved_path,
+    }
+  }
+
+  /// 记录一次查询，最新的在最前面
+  pub fn record(&self, entry: HistoryEntry) -> anyhow::Result<()> {
+    let mut entries = self.entries.lock().unwrap();
+    entries.insert(0, entry);
+    entries.truncate(MAX_HISTORY);
+    save_json(self.history_path.as_deref(), &*entries)
+  }
+
+  pub fn entries(&self, limit: Option<usize>) -> Vec<HistoryEntry> {
+    let entries = self.entries.lock().unwrap();
+    entries
+      .iter()
+      .take(limit.unwrap_or(entries.len()))
+      .cloned()
+      .collect()
+  }
+
+  pub fn clear(&self) -> anyhow::Result<()> {
+    let mut entries = self.entries.lock().unwrap();
+    entries.clear();
+    save_json(self.history_path.as_deref(), &*entries)
+  }
+
+  /// 保存一个命名查询，同名的会被覆盖
+  pub fn save_query(&self, name: String, sql: String) -> anyhow::Result<SavedQuery> {
+    let query = SavedQuery {
+      name,
+      sql,
+      saved_at: now(),
+    };
+    let mut saved = self.saved.lock().unwrap();
+    saved.retain(|v| v.name != query.name);
+    saved.push(query.clone());
+    saved.sort_by(|a, b| a.name.cmp(&b.name));
+    save_json(self.saved_path.as_deref(), &*saved)?;
+    Ok(query)
+  }
+
+  pub fn saved_queries(&self) -> Vec<SavedQuery> {
+    self.saved.lock().unwrap().clone()
+  }
+
+  /// 删除一个命名查询，返回是否找到了它
+  pub fn delete_query(&self, name: &str) -> anyhow::Result<bool> {
+    let mut saved = self.saved.lock().unwrap();
+    let len = saved.len();
+    saved.retain(|v| v.name != name);
+    save_json(self.saved_path.as_deref(), &*saved)?;
+    Ok(saved.len() != len)
+  }
+}
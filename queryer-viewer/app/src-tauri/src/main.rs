@@ -3,20 +3,300 @@
   windows_subsystem = "windows"
 )]
 
+mod chart;
+mod config;
+mod editor;
+mod history;
+mod sources;
+mod store;
+
+use chart::{ChartData, ChartRequest};
+use editor::{SqlCompletion, SqlDiagnostic};
+use futures::future::Abortable;
+use history::{History, HistoryEntry, SavedQuery};
+use queryer::OutputFormat;
+use sources::{data_files, Preview, RecentSource, Sources, DATA_EXTENSIONS};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use store::{Page, QueryResult, ResultStore};
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::{Manager, State};
+use tracing::warn;
+
+/// 第一页默认返回的行数
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// 预览数据源时默认返回的行数
+const DEFAULT_PREVIEW_ROWS: usize = 20;
+
 #[tauri::command]
 fn example_sql() -> String {
   queryer::example_sql()
 }
 
+/// 执行查询，结果保存在后端，只返回 schema 和第一页数据。
+/// query_id 由前端生成，用来之后翻页或者取消查询
+#[tauri::command]
+async fn query(
+  query_id: String,
+  sql: String,
+  page_size: Option<usize>,
+  store: State<'_, ResultStore>,
+  sources: State<'_, Sources>,
+  history: State<'_, History>,
+) -> Result<QueryResult, String> {
+  let session = sources.session();
+  let (run, registration) = store.start(&query_id);
+  let started_at = config::now();
+  let start = Instant::now();
+  let result = match Abortable::new(session.query(&sql), registration).await {
+    Ok(data) => data.map_err(|err| err.to_string()),
+    Err(_) => Err(format!("query {} cancelled", query_id)),
+  };
+  store.done(&query_id, run);
+
+  // 不管成功与否都记录到历史中，记录失败不影响查询结果
+  let entry = HistoryEntry {
+    sql,
+    started_at,
+    duration_ms: start.elapsed().as_millis() as u64,
+    rows: result.as_ref().ok().map(|data| data.height()),
+    error: result.as_ref().err().cloned(),
+  };
+  if let Err(err) = history.record(entry) {
+    warn!("failed to save query history: {}", err);
+  }
+
+  let data = store.insert(&query_id, result?);
+  QueryResult::new(query_id, &data, page_size.unwrap_or(DEFAULT_PAGE_SIZE))
+    .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn fetch_page(
+  query_id: String,
+  offset: usize,
+  len: usize,
+  store: State<'_, ResultStore>,
+) -> Result<Page, String> {
+  let data = store
+    .get(&query_id)
+    .ok_or_else(|| format!("query {} not found", query_id))?;
+  Page::new(&data, offset, len).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn cancel_query(query_id: String, store: State<'_, ResultStore>) -> bool {
+  store.cancel(&query_id)
+}
+
+/// 前端不再展示这个结果时调用，释放内存
+#[tauri::command]
+fn close_query(query_id: String, store: State<'_, ResultStore>) -> bool {
+  store.remove(&query_id)
+}
+
+/// 把查询结果写入文件，format 可以是 csv / json / parquet
+#[tauri::command]
+async fn export_result(
+  query_id: String,
+  path: String,
+  format: String,
+  store: State<'_, ResultStore>,
+) -> Result<(), String> {
+  let format: OutputFormat = format
+    .parse()
+    .map_err(|err: anyhow::Error| err.to_string())?;
+  let data = store
+    .get(&query_id)
+    .ok_or_else(|| format!("query {} not found", query_id))?;
+  data
+    .write_to_file(path, format)
+    .map_err(|err| err.to_string())
+}
+
+/// 校验 SQL 但不执行，返回语法错误和暂不支持的特性
+#[tauri::command]
+fn validate_sql(sql: String) -> Vec<SqlDiagnostic> {
+  editor::validate(&sql)
+}
+
+/// 根据光标位置（字符偏移）补全关键字、已注册的表名和前端已知的列名
+#[tauri::command]
+fn complete_sql(
+  sql: String,
+  cursor: usize,
+  columns: Option<Vec<String>>,
+  sources: State<'_, Sources>,
+) -> Vec<SqlCompletion> {
+  let mut catalog = sources.session().catalog();
+  catalog.columns = columns.unwrap_or_default();
+  editor::complete(&sql, cursor, &catalog)
+}
+
+/// 在后端聚合查询结果，只把画图需要的点传给前端
+#[tauri::command]
+async fn chart(
+  query_id: String,
+  spec: ChartRequest,
+  store: State<'_, ResultStore>,
+) -> Result<ChartData, String> {
+  let data = store
+    .get(&query_id)
+    .ok_or_else(|| format!("query {} not found", query_id))?;
+  ChartData::new(&data, spec).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn query_history(limit: Option<usize>, history: State<'_, History>) -> Vec<HistoryEntry> {
+  history.entries(limit)
+}
+
+#[tauri::command]
+fn clear_history(history: State<'_, History>) -> Result<(), String> {
+  history.clear().map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn save_query(
+  name: String,
+  sql: String,
+  history: State<'_, History>,
+) -> Result<SavedQuery, String> {
+  history.save_query(name, sql).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn saved_queries(history: State<'_, History>) -> Vec<SavedQuery> {
+  history.saved_queries()
+}
+
+#[tauri::command]
+fn delete_saved_query(name: String, history: State<'_, History>) -> Result<bool, String> {
+  history.delete_query(&name).map_err(|err| err.to_string())
+}
+
+/// 弹出文件选择框，选中的文件注册成表并返回预览；用户取消时返回 None
+#[tauri::command]
+async fn open_file(
+  name: Option<String>,
+  rows: Option<usize>,
+  sources: State<'_, Sources>,
+) -> Result<Option<Preview>, String> {
+  let path = FileDialogBuilder::new()
+    .add_filter("Data", DATA_EXTENSIONS)
+    .pick_file();
+  match path {
+    Some(path) => register_and_preview(&sources, &path, name, rows)
+      .await
+      .map(Some),
+    None => Ok(None),
+  }
+}
+
+/// 弹出文件夹选择框，文件夹下所有数据文件都注册成表
+#[tauri::command]
+async fn open_folder(sources: State<'_, Sources>) -> Result<Vec<RecentSource>, String> {
+  let dir = match FileDialogBuilder::new().pick_folder() {
+    Some(dir) => dir,
+    None => return Ok(Vec::new()),
+  };
+  data_files(&dir)
+    .and_then(|files| {
+      files
+        .iter()
+        .map(|path| sources.register_file(path, None))
+        .collect()
+    })
+    .map_err(|err| err.to_string())
+}
+
+/// 注册一个本地文件，比如拖拽到窗口中的文件，返回预览
+#[tauri::command]
+async fn register_source(
+  path: String,
+  name: Option<String>,
+  rows: Option<usize>,
+  sources: State<'_, Sources>,
+) -> Result<Preview, String> {
+  register_and_preview(&sources, &PathBuf::from(path), name, rows).await
+}
+
+/// 预览一个已经注册的表
 #[tauri::command]
-async fn query(sql: String) -> Result<String, String> {
-  let data = queryer::query(&sql).await.map_err(|err| err.to_string())?;
-  Ok(data.to_csv().map_err(|err| err.to_string())?)
+async fn preview_source(
+  name: String,
+  rows: Option<usize>,
+  sources: State<'_, Sources>,
+) -> Result<Preview, String> {
+  let source = sources
+    .recent()
+    .into_iter()
+    .find(|v| v.name == name)
+    .ok_or_else(|| format!("source {} not found", name))?;
+  preview(&sources, source, rows).await
+}
+
+#[tauri::command]
+fn recent_sources(sources: State<'_, Sources>) -> Vec<RecentSource> {
+  sources.recent()
+}
+
+async fn register_and_preview(
+  sources: &Sources,
+  path: &Path,
+  name: Option<String>,
+  rows: Option<usize>,
+) -> Result<Preview, String> {
+  let source = sources
+    .register_file(path, name)
+    .map_err(|err| err.to_string())?;
+  preview(sources, source, rows).await
+}
+
+async fn preview(
+  sources: &Sources,
+  source: RecentSource,
+  rows: Option<usize>,
+) -> Result<Preview, String> {
+  let data = sources
+    .session()
+    .load(&source.name)
+    .await
+    .map_err(|err| err.to_string())?;
+  Preview::new(source, &data, rows.unwrap_or(DEFAULT_PREVIEW_ROWS)).map_err(|err| err.to_string())
 }
 
 fn main() {
   tauri::Builder::default()
-    .invoke_handler(tauri::generate_handler![example_sql, query])
+    .manage(ResultStore::default())
+    .setup(|app| {
+      // 恢复上次打开过的数据源、查询历史和保存的查询
+      let config_dir = app.path_resolver().app_config_dir();
+      app.manage(Sources::load(config_dir.clone()));
+      app.manage(History::load(config_dir));
+      Ok(())
+    })
+    .invoke_handler(tauri::generate_handler![
+      example_sql,
+      query,
+      fetch_page,
+      cancel_query,
+      close_query,
+      export_result,
+      chart,
+      validate_sql,
+      complete_sql,
+      query_history,
+      clear_history,
+      save_query,
+      saved_queries,
+      delete_saved_query,
+      open_file,
+      open_folder,
+      register_source,
+      preview_source,
+      recent_sources
+    ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
 }
@@ -0,0 +1,161 @@
+use crate::config::{load_json, now, save_json};
+use crate::store::Page;
+use queryer::{DataSet, Session};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use url::Url;
+
+/// 最多记住多少个最近打开的数据源
+const MAX_RECENT: usize = 20;
+/// 保存最近数据源的文件名，位于应用的配置目录下
+const RECENT_FILE: &str = "recent_sources.json";
+/// 打开文件夹时，只注册这些扩展名的文件
+pub const DATA_EXTENSIONS: &[&str] = &["csv", "gz", "zst", "bz2", "xz", "zip"];
+
+/// 最近打开过的数据源
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecentSource {
+  pub name: String,
+  pub url: String,
+  pub opened_at: u64,
+}
+
+/// 已注册的数据源，重启之后从配置目录恢复
+pub struct Sources {
+  session: Mutex<Session>,
+  recent: Mutex<Vec<RecentSource>>,
+  path: Option<PathBuf>,
+}
+
+impl Sources {
+  /// 从配置目录读取最近的数据源，并重新注册成表
+  pub fn load(config_dir: Option<PathBuf>) -> Self {
+    let path = config_dir.map(|dir| dir.join(RECENT_FILE));
+    let recent: Vec<RecentSource> = load_json(path.as_deref());
+
+    let mut session = Session::new();
+    for source in &recent {
+      session.register(&source.name, &source.url);
+    }
+
+    Self {
+      session: Mutex::new(session),
+      recent: Mutex::new(recent),
+      path,
+    }
+  }
+
+  /// 注册一个本地文件，返回表名
+  pub fn register_file(&self, path: &Path, name: Option<String>) -> anyhow::Result<RecentSource> {
+    let name = name.unwrap_or_else(|| table_name(path));
+    // 文件名里的空格、# 等字符要 percent-encode，否则会被当成数据源地址的一部分
+    let url = Url::from_file_path(path)
+      .map_err(|_| anyhow::anyhow!("Invalid file path: {}", path.display()))?;
+    self.register(name, url.to_string())
+  }
+
+  /// 把数据源注册成表，并记录到最近打开的数据源中
+  pub fn register(&self, name: String, url: String) -> anyhow::Result<RecentSource> {
+    self.session.lock().unwrap().register(&name, &url);
+
+    let source = RecentSource {
+      name,
+      url,
+      opened_at: now(),
+    };
+    let mut recent = self.recent.lock().unwrap();
+    recent.retain(|v| v.name != source.name && v.url != source.url);
+    recent.insert(0, source.clone());
+    recent.truncate(MAX_RECENT);
+    save_json(self.path.as_deref(), &*recent)?;
+    Ok(source)
+  }
+
+  /// 当前会话的一个副本，查询时不需要一直持有锁
+  pub fn session(&self) -> Session {
+    self.session.lock().unwrap().clone()
+  }
+
+  pub fn recent(&self) -> Vec<RecentSource> {
+    self.recent.lock().unwrap().clone()
+  }
+}
+
+/// 文件夹下所有数据文件
+pub fn data_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+  let mut files = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    let is_data = path
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .map(|ext| DATA_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+      .unwrap_or(false);
+    if path.is_file() && is_data {
+      files.push(path);
+    }
+  }
+  files.sort();
+  Ok(files)
+}
+
+/// 根据文件名生成一个合法的表名，比如 `owid-covid-latest.csv.gz` -> `owid_covid_latest`
+fn table_name(path: &Path) -> String {
+  let file_name = path
+    .file_name()
+    .map(|v| v.to_string_lossy().to_string())
+    .unwrap_or_default();
+  let stem = file_name.split('.').next().unwrap_or_default();
+  let name: String = stem
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect();
+  match name.chars().next() {
+    Some(c) if c.is_ascii_alphabetic() => name,
+    _ => format!("t_{}", name),
+  }
+}
+
+/// 选中数据源之后立即展示的预览：schema 和前几行数据
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Preview {
+  pub source: RecentSource,
+  pub columns: Vec<SourceColumn>,
+  pub total_rows: usize,
+  pub rows: Page,
+}
+
+/// 预览中的一列
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceColumn {
+  pub name: String,
+  pub dtype: String,
+  pub null_count: usize,
+  pub samples: Vec<String>,
+}
+
+impl Preview {
+  pub fn new(source: RecentSource, data: &DataSet, rows: usize) -> anyhow::Result<Self> {
+    let columns = data
+      .columns_info()
+      .into_iter()
+      .map(|c| SourceColumn {
+        name: c.name,
+        dtype: c.dtype,
+        null_count: c.null_count,
+        samples: c.samples,
+      })
+      .collect();
+    Ok(Self {
+      source,
+      columns,
+      total_rows: data.height(),
+      rows: Page::new(data, 0, rows)?,
+    })
+  }
+}
@@ -0,0 +1,139 @@
+use futures::future::{AbortHandle, AbortRegistration};
+use queryer::DataSet;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// 保存在后端的查询结果。前端按页获取数据，避免把整个结果一次性传给 webview
+#[derive(Default)]
+pub struct ResultStore {
+  results: Mutex<HashMap<String, Arc<DataSet>>>,
+  /// 同一个 query_id 可能被重新执行，用每次执行的序号区分
+  running: Mutex<HashMap<String, (u64, AbortHandle)>>,
+  next_run: AtomicU64,
+}
+
+impl ResultStore {
+  /// 记录一个正在执行的查询，返回这次执行的序号，以及用来在取消时中断查询的 registration。
+  /// 同一个 query_id 之前的执行会被中断
+  pub fn start(&self, query_id: &str) -> (u64, AbortRegistration) {
+    let (handle, registration) = AbortHandle::new_pair();
+    let run = self.next_run.fetch_add(1, Ordering::Relaxed);
+    if let Some((_, old)) = self
+      .running
+      .lock()
+      .unwrap()
+      .insert(query_id.to_owned(), (run, handle))
+    {
+      old.abort();
+    }
+    (run, registration)
+  }
+
+  /// 查询结束（不管成功与否），不再需要取消。被中断的旧查询不会影响重新执行的查询
+  pub fn done(&self, query_id: &str, run: u64) {
+    let mut running = self.running.lock().unwrap();
+    if matches!(running.get(query_id), Some((current, _)) if *current == run) {
+      running.remove(query_id);
+    }
+  }
+
+  /// 取消正在执行的查询，返回是否找到了这个查询
+  pub fn cancel(&self, query_id: &str) -> bool {
+    match self.running.lock().unwrap().remove(query_id) {
+      Some((_, handle)) => {
+        handle.abort();
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn insert(&self, query_id: &str, data: DataSet) -> Arc<DataSet> {
+    let data = Arc::new(data);
+    self
+      .results
+      .lock()
+      .unwrap()
+      .insert(query_id.to_owned(), data.clone());
+    data
+  }
+
+  pub fn get(&self, query_id: &str) -> Option<Arc<DataSet>> {
+    self.results.lock().unwrap().get(query_id).cloned()
+  }
+
+  /// 前端不再需要这个结果时释放内存
+  pub fn remove(&self, query_id: &str) -> bool {
+    self.results.lock().unwrap().remove(query_id).is_some()
+  }
+}
+
+/// 结果中的一列
+#[derive(Debug, Serialize)]
+pub struct Column {
+  pub name: String,
+  pub dtype: String,
+}
+
+/// 一页数据，每行是按列顺序排列的值
+#[derive(Debug, Serialize)]
+pub struct Page {
+  pub offset: usize,
+  pub rows: Vec<Vec<Value>>,
+}
+
+/// query 命令返回给前端的结果：schema、总行数和第一页数据
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryResult {
+  pub query_id: String,
+  pub columns: Vec<Column>,
+  pub total_rows: usize,
+  pub first_page: Page,
+}
+
+impl QueryResult {
+  pub fn new(query_id: String, data: &DataSet, page_size: usize) -> anyhow::Result<Self> {
+    Ok(Self {
+      query_id,
+      columns: columns(data),
+      total_rows: data.height(),
+      first_page: Page::new(data, 0, page_size)?,
+    })
+  }
+}
+
+impl Page {
+  pub fn new(data: &DataSet, offset: usize, len: usize) -> anyhow::Result<Self> {
+    let names: Vec<&str> = data.get_column_names();
+    let page = data.page(offset, len);
+    // to_json 生成的是对象数组，这里转换成按列顺序的数组，减少传输的数据量
+    let rows = match serde_json::from_str(&page.to_json()?)? {
+      Value::Array(objects) => objects
+        .into_iter()
+        .map(|mut obj| {
+          names
+            .iter()
+            .map(|name| obj.get_mut(*name).map(Value::take).unwrap_or(Value::Null))
+            .collect()
+        })
+        .collect(),
+      _ => Vec::new(),
+    };
+    Ok(Self { offset, rows })
+  }
+}
+
+fn columns(data: &DataSet) -> Vec<Column> {
+  data
+    .get_columns()
+    .iter()
+    .map(|s| Column {
+      name: s.name().to_owned(),
+      dtype: s.dtype().to_string(),
+    })
+    .collect()
+}
@@ -0,0 +1,272 @@
+use crate::summary::is_numeric;
+use crate::time::{to_datetimes, to_strings, TimeBucket};
+use crate::DataSet;
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::str::FromStr;
+
+/// 分组用的临时列名
+const KEY: &str = "__x";
+
+/// y 轴的聚合方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Mean,
+    Min,
+    Max,
+}
+
+impl FromStr for Aggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "count" => Ok(Aggregation::Count),
+            "sum" => Ok(Aggregation::Sum),
+            "mean" | "avg" => Ok(Aggregation::Mean),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            v => Err(anyhow!("Aggregation {} not supported", v)),
+        }
+    }
+}
+
+impl Aggregation {
+    fn expr(&self, name: &str) -> Expr {
+        match self {
+            Aggregation::Count => col(name).count(),
+            Aggregation::Sum => col(name).sum(),
+            Aggregation::Mean => col(name).mean(),
+            Aggregation::Min => col(name).min(),
+            Aggregation::Max => col(name).max(),
+        }
+    }
+}
+
+/// 图表的描述：x 轴用哪一列分组，y 轴有哪些列，怎么聚合
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartSpec {
+    pub x: String,
+    /// 为空时统计每组的行数，比如直方图
+    pub y: Vec<String>,
+    pub aggregation: Aggregation,
+    /// 数值分桶（直方图）：x 按 bin_size 向下取整后分组
+    pub bin_size: Option<f64>,
+    /// 时间分桶：x 截断到所在的天 / 周 / 月 / 季度 / 年后分组
+    pub time_bucket: Option<TimeBucket>,
+}
+
+impl ChartSpec {
+    /// 默认按 x 分组统计行数
+    pub fn new(x: impl Into<String>) -> Self {
+        Self {
+            x: x.into(),
+            y: Vec::new(),
+            aggregation: Aggregation::Count,
+            bin_size: None,
+            time_bucket: None,
+        }
+    }
+}
+
+/// x 轴的取值：数值，或者文本标签（包括时间分桶后的日期）
+#[derive(Debug, Clone, PartialEq)]
+pub enum Axis {
+    Numbers(Vec<Option<f64>>),
+    Labels(Vec<Option<String>>),
+}
+
+/// 一条 y 轴数据
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartSeries {
+    pub name: String,
+    pub values: Vec<Option<f64>>,
+}
+
+/// 聚合后的图表数据，每个 series 的长度和 x 轴一致
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chart {
+    pub x: Axis,
+    pub series: Vec<ChartSeries>,
+}
+
+impl DataSet {
+    /// 按照图表描述聚合数据，由 polars 完成分组和聚合，只返回画图需要的点
+    pub fn chart(&self, spec: &ChartSpec) -> Result<Chart> {
+        let key = key_series(&self.0, spec)?;
+        let numeric = is_numeric(key.dtype());
+        let mut df = self.0.clone();
+        df.with_column(key)?;
+
+        let (names, aggs): (Vec<String>, Vec<Expr>) = if spec.y.is_empty() {
+            let name = "count".to_owned();
+            let agg = col(&spec.x).count().cast(DataType::Float64).alias(&name);
+            (vec![name], vec![agg])
+        } else {
+            spec.y
+                .iter()
+                .map(|y| {
+                    let agg = spec.aggregation.expr(y).cast(DataType::Float64).alias(y);
+                    (y.to_owned(), agg)
+                })
+                .unzip()
+        };
+
+        // 日期要用 to_strings 格式化，polars 不支持把日期 cast 成字符串
+        let key_expr = if numeric {
+            col(KEY).cast(DataType::Float64)
+        } else {
+            col(KEY).map(to_strings, Some(DataType::Utf8))
+        };
+        let grouped = df
+            .lazy()
+            .groupby(vec![col(KEY)])
+            .agg(aggs)
+            .sort(KEY, false)
+            .with_column(key_expr)
+            .collect()?;
+
+        let key = grouped.column(KEY)?;
+        let x = if numeric {
+            Axis::Numbers(key.f64()?.into_iter().collect())
+        } else {
+            Axis::Labels(
+                key.utf8()?
+                    .into_iter()
+                    .map(|v| v.map(|v| v.to_owned()))
+                    .collect(),
+            )
+        };
+        let series = names
+            .into_iter()
+            .map(|name| {
+                let values = grouped.column(&name)?.f64()?.into_iter().collect();
+                Ok(ChartSeries { name, values })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Chart { x, series })
+    }
+}
+
+/// 计算用来分组的 key：原始的 x，数值分桶后的 x，或者时间分桶后的日期
+fn key_series(df: &DataFrame, spec: &ChartSpec) -> Result<Series> {
+    let key = match (spec.bin_size, spec.time_bucket) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow!("bin_size and time_bucket cannot be used together"))
+        }
+        (Some(bin), None) => {
+            if bin <= 0.0 {
+                return Err(anyhow!("bin_size should be positive, got {}", bin));
+            }
+            let values = cast_column(df, &spec.x, DataType::Float64)?;
+            let keys: Vec<Option<f64>> = values
+                .f64()?
+                .into_iter()
+                .map(|v| v.map(|v| (v / bin).floor() * bin))
+                .collect();
+            Series::new(KEY, keys)
+        }
+        (None, Some(bucket)) => {
+            let keys: Vec<Option<String>> = to_datetimes(df.column(&spec.x)?)?
+                .into_iter()
+                .map(|v| v.map(|dt| bucket.truncate(dt).format("%Y-%m-%d").to_string()))
+                .collect();
+            Series::new(KEY, keys)
+        }
+        (None, None) => {
+            let mut key = df.column(&spec.x)?.clone();
+            key.rename(KEY);
+            key
+        }
+    };
+    Ok(key)
+}
+
+fn cast_column(df: &DataFrame, name: &str, dtype: DataType) -> Result<Series> {
+    let df = df
+        .clone()
+        .lazy()
+        .select(vec![col(name).cast(dtype)])
+        .collect()?;
+    Ok(df.column(name)?.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::parse_dates;
+
+    fn data() -> DataSet {
+        let df = DataFrame::new(vec![
+            Series::new(
+                "date",
+                &["2021-08-02", "2021-08-03", "2021-08-10", "2021-09-01"],
+            ),
+            Series::new("cases", &[1i64, 2, 3, 4]),
+        ])
+        .unwrap();
+        DataSet(df)
+    }
+
+    #[test]
+    fn time_bucket_chart_should_work() {
+        let spec = ChartSpec {
+            y: vec!["cases".into()],
+            aggregation: Aggregation::Sum,
+            time_bucket: Some(TimeBucket::Month),
+            ..ChartSpec::new("date")
+        };
+        let chart = data().chart(&spec).unwrap();
+        assert_eq!(
+            chart.x,
+            Axis::Labels(vec![Some("2021-08-01".into()), Some("2021-09-01".into())])
+        );
+        assert_eq!(chart.series[0].values, vec![Some(6.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn date_key_chart_should_work() {
+        let ds = DataSet(parse_dates(data().0).unwrap());
+        assert_eq!(ds.column("date").unwrap().dtype(), &DataType::Date32);
+        let spec = ChartSpec {
+            y: vec!["cases".into()],
+            aggregation: Aggregation::Sum,
+            ..ChartSpec::new("date")
+        };
+        let chart = ds.chart(&spec).unwrap();
+        assert_eq!(
+            chart.x,
+            Axis::Labels(vec![
+                Some("2021-08-02".into()),
+                Some("2021-08-03".into()),
+                Some("2021-08-10".into()),
+                Some("2021-09-01".into())
+            ])
+        );
+        assert_eq!(
+            chart.series[0].values,
+            vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0)]
+        );
+    }
+
+    #[test]
+    fn histogram_should_work() {
+        let spec = ChartSpec {
+            bin_size: Some(2.0),
+            ..ChartSpec::new("cases")
+        };
+        let chart = data().chart(&spec).unwrap();
+        assert_eq!(
+            chart.x,
+            Axis::Numbers(vec![Some(0.0), Some(2.0), Some(4.0)])
+        );
+        assert_eq!(chart.series[0].name, "count");
+        assert_eq!(
+            chart.series[0].values,
+            vec![Some(1.0), Some(2.0), Some(1.0)]
+        );
+    }
+}
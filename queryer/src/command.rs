@@ -0,0 +1,125 @@
+use crate::sample;
+use crate::TyrDialect;
+use anyhow::{anyhow, Result};
+use sqlparser::{ast::Statement, parser::Parser};
+
+/// queryer 支持的语句：除了标准的 SELECT，还有一些方便数据探索的扩展语句
+#[derive(Debug)]
+pub(crate) enum Command {
+    /// SELECT ... FROM ... WHERE ...
+    Select(Statement),
+    /// DESCRIBE <source> 或者 SHOW COLUMNS FROM <source>
+    Describe(String),
+    /// SUMMARIZE <source> 或者 SUMMARIZE (SELECT ...)，对查询结果的每一列做统计
+    Summarize(Statement),
+    /// EXPLAIN SELECT ...，返回查询计划，包括每个数据源下推了哪些列和条件
+    Explain(Statement),
+}
+
+impl Command {
+    pub fn parse(sql: &str) -> Result<Self> {
+        let sql = sql.trim().trim_end_matches(';').trim_end();
+
+        // sqlparser 不认识 DESCRIBE <table>，我们自己处理
+        if let Some(source) = strip_keyword(sql, "DESCRIBE").or_else(|| strip_keyword(sql, "DESC"))
+        {
+            return Ok(Command::Describe(unquote_source(source)));
+        }
+
+        if let Some(rest) = strip_keyword(sql, "EXPLAIN") {
+            return Ok(Command::Explain(parse_single(rest)?));
+        }
+
+        if let Some(rest) = strip_keyword(sql, "SUMMARIZE") {
+            let subquery = match rest.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+                Some(v) => v.to_owned(),
+                None if strip_keyword(rest, "SELECT").is_some() => rest.to_owned(),
+                None => format!("SELECT * FROM {}", rest),
+            };
+            return Ok(Command::Summarize(parse_single(&subquery)?));
+        }
+
+        match parse_single(sql)? {
+            Statement::ShowColumns { table_name, .. } => {
+                Ok(Command::Describe(table_name.0[0].value.clone()))
+            }
+            statement => Ok(Command::Select(statement)),
+        }
+    }
+}
+
+/// 解析单条 SQL 语句
+fn parse_single(sql: &str) -> Result<Statement> {
+    let sql = sample::rewrite(sql)?;
+    let mut ast = Parser::parse_sql(&TyrDialect, &sql)?;
+    if ast.len() != 1 {
+        return Err(anyhow!("Only support single sql at the moment"));
+    }
+    Ok(ast.pop().unwrap())
+}
+
+/// 去掉数据源两边的引号，或者取出 read_url('...') 里的 url
+fn unquote_source(source: &str) -> String {
+    let inner = |open: &str, close: &str| {
+        source
+            .strip_prefix(open)
+            .and_then(|v| v.strip_suffix(close))
+            .map(|v| v.replace(&close.repeat(2), close))
+    };
+    let read_url = source
+        .get(..9)
+        .filter(|v| v.eq_ignore_ascii_case("read_url("))
+        .and_then(|_| source[9..].trim().strip_prefix('\''))
+        .and_then(|v| v.trim_end().strip_suffix(')'))
+        .and_then(|v| v.trim_end().strip_suffix('\''))
+        .map(|v| v.replace("''", "'"));
+    read_url
+        .or_else(|| inner("\"", "\""))
+        .or_else(|| inner("`", "`"))
+        .or_else(|| inner("'", "'"))
+        .unwrap_or_else(|| source.to_owned())
+}
+
+/// 如果 sql 以 keyword 开头（不区分大小写），返回 keyword 之后的部分
+fn strip_keyword<'a>(sql: &'a str, keyword: &str) -> Option<&'a str> {
+    let (word, rest) = sql.split_once(char::is_whitespace)?;
+    word.eq_ignore_ascii_case(keyword).then(|| rest.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_describe_should_work() {
+        let url = "file:///tmp/data.csv";
+        for sql in [
+            format!("DESCRIBE {}", url),
+            format!("desc {};", url),
+            format!("SHOW COLUMNS FROM {}", url),
+            format!("DESCRIBE \"{}\"", url),
+            format!("DESCRIBE read_url('{}')", url),
+            format!("SHOW COLUMNS FROM `{}`", url),
+        ] {
+            match Command::parse(&sql).unwrap() {
+                Command::Describe(source) => assert_eq!(source, url),
+                v => panic!("expect describe, got {:?}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_summarize_should_work() {
+        let url = "file:///tmp/data.csv";
+        for sql in [
+            format!("SUMMARIZE {}", url),
+            format!("summarize (SELECT a, b FROM {} WHERE a > 1)", url),
+            format!("SUMMARIZE SELECT a FROM {}", url),
+        ] {
+            assert!(matches!(
+                Command::parse(&sql).unwrap(),
+                Command::Summarize(Statement::Query(_))
+            ));
+        }
+    }
+}
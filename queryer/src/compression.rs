@@ -0,0 +1,169 @@
+use crate::excel::SheetFormat;
+use anyhow::{anyhow, Result};
+use std::io::{Cursor, Read};
+
+/// 数据源支持的压缩格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+    Zip,
+}
+
+impl Compression {
+    /// 先根据扩展名判断压缩格式，判断不出来再看数据开头的 magic bytes
+    pub fn detect(name: &str, data: &[u8]) -> Self {
+        match Self::from_extension(name) {
+            Compression::None => Self::from_magic(data),
+            v => v,
+        }
+    }
+
+    fn from_extension(name: &str) -> Self {
+        // 去掉 url 里的 query string，只看路径部分
+        let path = name.split('?').next().unwrap_or(name).to_lowercase();
+        match path.rsplit('.').next() {
+            Some("gz") | Some("gzip") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            Some("zip") => Compression::Zip,
+            _ => Compression::None,
+        }
+    }
+
+    fn from_magic(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            [b'P', b'K', 0x03, 0x04, ..] => Compression::Zip,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// 如果数据是压缩过的，就解压；zip 归档可以用 entry 指定要读取的文件。
+/// 最多解压出 limit + 1 个字节，调用者据此判断是否超过限制，避免解压炸弹耗尽内存
+pub fn decompress(
+    name: &str,
+    data: Vec<u8>,
+    entry: Option<&str>,
+    limit: Option<u64>,
+) -> Result<Vec<u8>> {
+    let buf = match Compression::detect(name, &data) {
+        Compression::None => return Ok(data),
+        Compression::Gzip => read_limited(flate2::read::MultiGzDecoder::new(&data[..]), limit)?,
+        Compression::Zstd => read_limited(zstd::stream::read::Decoder::new(&data[..])?, limit)?,
+        Compression::Bzip2 => read_limited(bzip2::read::MultiBzDecoder::new(&data[..]), limit)?,
+        Compression::Xz => read_limited(xz2::read::XzDecoder::new_multi_decoder(&data[..]), limit)?,
+        // xlsx 和 ods 本身也是 zip，交给 ExcelLoader 处理
+        Compression::Zip if SheetFormat::detect(&data).is_some() => return Ok(data),
+        Compression::Zip => unzip(data, entry, limit)?,
+    };
+    Ok(buf)
+}
+
+fn read_limited(reader: impl Read, limit: Option<u64>) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let limit = limit.map_or(u64::MAX, |v| v.saturating_add(1));
+    reader.take(limit).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// 从 zip 归档中读出一个文件。只有一个文件时直接读取，多个文件时需要指定 entry
+fn unzip(data: Vec<u8>, entry: Option<&str>, limit: Option<u64>) -> Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let name = match entry {
+        Some(v) => v.to_owned(),
+        None => {
+            // 忽略目录和 macOS 打包时附带的元数据
+            let files: Vec<_> = archive
+                .file_names()
+                .filter(|v| !v.ends_with('/') && !v.starts_with("__MACOSX/"))
+                .map(|v| v.to_owned())
+                .collect();
+            match files.as_slice() {
+                [v] => v.to_owned(),
+                [] => return Err(anyhow!("zip archive is empty")),
+                _ => {
+                    return Err(anyhow!(
+                        "zip archive contains multiple files ({}), pick one with #entry=<name>",
+                        files.join(", ")
+                    ))
+                }
+            }
+        }
+    };
+
+    // 归档里记录的大小不可信，不能用来预先分配内存
+    let file = archive.by_name(&name)?;
+    read_limited(file, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const CSV: &str = "a,b\n1,2\n3,4\n";
+
+    #[test]
+    fn detect_should_work() {
+        assert_eq!(
+            Compression::detect("file:///a.csv.gz", &[]),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect("https://abc.xyz/a.zip?a=1", &[]),
+            Compression::Zip
+        );
+        assert_eq!(
+            Compression::detect("https://abc.xyz/data", &[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::detect("file:///a.csv", CSV.as_bytes()),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn decompress_gzip_should_work() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        let data = encoder.finish().unwrap();
+
+        // 没有扩展名时也能通过 magic bytes 识别
+        let result = decompress("https://abc.xyz/data", data, None, None).unwrap();
+        assert_eq!(result, CSV.as_bytes());
+    }
+
+    #[test]
+    fn decompress_should_stop_at_limit() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(&vec![0; 1 << 20]).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let result = decompress("file:///a.gz", data, None, Some(10)).unwrap();
+        assert_eq!(result.len(), 11);
+    }
+
+    #[test]
+    fn decompress_zip_should_pick_entry() {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a.csv", "b.csv"] {
+            writer.start_file(name, Default::default()).unwrap();
+            writer.write_all(CSV.as_bytes()).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        assert!(decompress("file:///a.zip", data.clone(), None, None).is_err());
+        let result = decompress("file:///a.zip", data, Some("b.csv"), None).unwrap();
+        assert_eq!(result, CSV.as_bytes());
+    }
+}
@@ -1,18 +1,79 @@
+use crate::functions::{self, GapFill};
+use crate::pushdown::Pushdown;
+use crate::reshape::Reshape;
+use crate::sample::Sample;
+use crate::time::{parse_datetime, to_strings};
+use crate::udf;
 use anyhow::{anyhow, Result};
 use polars::prelude::*;
 use sqlparser::ast::{
-    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, Offset as SqlOffset, OrderByExpr, Select,
-    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value as SqlValue,
+    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, Function, FunctionArg, Ident,
+    JoinConstraint, JoinOperator, ObjectName, Offset as SqlOffset, OrderByExpr, Select, SelectItem,
+    SetExpr, Statement, TableFactor, TableWithJoins, UnaryOperator, Value as SqlValue,
 };
 
 /// 解析出来的 SQL 信息
 pub struct Sql<'a> {
     pub(crate) selection: Vec<Expr>,
     pub(crate) condition: Option<Expr>,
+    /// 原始的 WHERE 条件，用于下推到数据库类型的数据源
+    pub(crate) raw_condition: Option<String>,
+    /// 原始的 WHERE 条件按 AND 拆开，join 时分别决定能下推到哪个数据源
+    pub(crate) conjuncts: Vec<&'a SqlExpr>,
     pub(crate) source: &'a str,
-    pub(crate) order_by: Vec<(String, bool)>,
+    /// 第一个数据源的别名，或者注册的表名
+    pub(crate) alias: Option<String>,
+    /// 第一个数据源的 pivot / unpivot
+    pub(crate) reshape: Option<Reshape>,
+    /// 第一个数据源的 TABLESAMPLE
+    pub(crate) sample: Option<Sample>,
+    pub(crate) joins: Vec<Join<'a>>,
+    /// 查询用到的所有列，有 * 或者无法分析的表达式时是 None
+    pub(crate) columns: Option<Vec<String>>,
+    /// (列名, 是否降序, NULLS FIRST / NULLS LAST)
+    pub(crate) order_by: Vec<(String, bool, Option<bool>)>,
     pub(crate) offset: Option<i64>,
     pub(crate) limit: Option<usize>,
+    /// 投影中有 time_bucket_gapfill 时，需要补齐的列
+    pub(crate) gap_fill: Option<GapFill>,
+    /// 调用的自定义函数，参数里的调用排在前面
+    pub(crate) udfs: Vec<&'a Function>,
+}
+
+impl<'a> Sql<'a> {
+    /// 下推到数据源的 WHERE / ORDER BY / LIMIT。WHERE 中有不能下推的条件时，
+    /// 数据源返回的行之后还要过滤，这时先排序截断会丢掉满足条件的行，只能都不下推
+    pub(crate) fn pushdown(&self) -> Pushdown {
+        match self.raw_condition.is_some() == self.condition.is_some() {
+            true => Pushdown::new(
+                self.raw_condition.clone(),
+                &self.order_by,
+                self.offset,
+                self.limit,
+            ),
+            false => Pushdown::default(),
+        }
+    }
+}
+
+/// FROM 中的一个数据源，以及在 SQL 中引用它的名字
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Relation<'a> {
+    pub(crate) source: &'a str,
+    pub(crate) alias: Option<String>,
+    /// 读入之后做的 pivot / unpivot
+    pub(crate) reshape: Option<Reshape>,
+    /// 读入之后马上做的抽样，在 pivot / unpivot 之前
+    pub(crate) sample: Option<Sample>,
+}
+
+/// JOIN 的数据源和连接条件
+#[derive(Debug, Clone)]
+pub(crate) struct Join<'a> {
+    pub(crate) relation: Relation<'a>,
+    pub(crate) kind: JoinType,
+    /// ON 中用 AND 连接的等值条件，两边都是列名
+    pub(crate) on: Vec<(String, String)>,
 }
 
 // 因为 Rust trait 的规则，如果要想对已有的类型实现已有的 trait，需要简单包装一下
@@ -48,15 +109,69 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
                     _ => return Err(anyhow!("We only support Select Query at the moment")),
                 };
 
-                let source = Source(table_with_joins).try_into()?;
+                let (relation, joins): (Relation, Vec<Join>) =
+                    Source(table_with_joins).try_into()?;
 
                 let condition = match where_clause {
                     Some(expr) => Some(Expression(Box::new(expr.to_owned())).try_into()?),
                     None => None,
                 };
 
+                // 只有简单的条件才下推，函数之类的数据库未必支持
+                let raw_condition = where_clause
+                    .as_ref()
+                    .filter(|expr| is_portable(expr))
+                    .map(|expr| expr.to_string());
+                let mut conjuncts = Vec::new();
+                if let Some(expr) = where_clause {
+                    split_conjuncts(expr, &mut conjuncts);
+                }
+
+                // 收集用到的列，之后只从数据源读取这些列
+                let mut columns = Some(Vec::new());
+                let exprs = projection
+                    .iter()
+                    .map(|p| match p {
+                        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                            Some(expr)
+                        }
+                        _ => None,
+                    })
+                    .chain(where_clause.iter().map(Some))
+                    .chain(orders.iter().map(|v| Some(&v.expr)));
+                for expr in exprs {
+                    columns = match (columns, expr) {
+                        (Some(mut v), Some(expr)) => collect_columns(expr, &mut v).then_some(v),
+                        _ => None,
+                    };
+                }
+                if let Some(v) = columns.as_mut() {
+                    for (left, right) in joins.iter().flat_map(|j| &j.on) {
+                        v.push(left.to_owned());
+                        v.push(right.to_owned());
+                    }
+                    v.sort();
+                    v.dedup();
+                }
+
+                let mut udfs = Vec::new();
+                for p in projection {
+                    if let SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } =
+                        p
+                    {
+                        udf::collect_calls(expr, &mut udfs);
+                    }
+                }
+                if let Some(expr) = where_clause {
+                    udf::collect_calls(expr, &mut udfs);
+                }
+
                 let mut selection = Vec::with_capacity(8);
+                let mut gap_fill = None;
                 for p in projection {
+                    if let Some(v) = functions::gap_fill(p)? {
+                        gap_fill = Some(v);
+                    }
                     let expr = Projection(p).try_into()?;
                     selection.push(expr);
                 }
@@ -72,10 +187,19 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
                 Ok(Sql {
                     selection,
                     condition,
-                    source,
+                    raw_condition,
+                    conjuncts,
+                    source: relation.source,
+                    alias: relation.alias,
+                    reshape: relation.reshape,
+                    sample: relation.sample,
+                    joins,
+                    columns,
                     order_by,
                     offset,
                     limit,
+                    gap_fill,
+                    udfs,
                 })
             }
             _ => Err(anyhow!("We only support select at the moment")),
@@ -89,15 +213,69 @@ impl TryFrom<Expression> for Expr {
 
     fn try_from(expr: Expression) -> Result<Self, Self::Error> {
         match *expr.0 {
-            SqlExpr::BinaryOp { left, op, right } => Ok(Expr::BinaryExpr {
-                left: Box::new(Expression(left).try_into()?),
-                op: Operation(op).try_into()?,
-                right: Box::new(Expression(right).try_into()?),
-            }),
+            SqlExpr::BinaryOp {
+                left,
+                op: op @ (SqlBinaryOperator::And | SqlBinaryOperator::Or),
+                right,
+            } => {
+                let left: Expr = Expression(left).try_into()?;
+                let right: Expr = Expression(right).try_into()?;
+                Ok(logical(left, right, op == SqlBinaryOperator::And))
+            }
+            SqlExpr::BinaryOp { left, op, right } => {
+                // 日期列和 '2021-08-01' 这样的字符串比较时，把日期列转成字符串再比较
+                let date_compare = is_date_literal(&left) || is_date_literal(&right);
+                let convert = |expr: Box<SqlExpr>| -> Result<Expr> {
+                    let is_column = matches!(*expr, SqlExpr::Identifier(_));
+                    let expr: Expr = Expression(expr).try_into()?;
+                    match date_compare && is_column {
+                        true => Ok(expr.map(to_strings, Some(DataType::Utf8))),
+                        false => Ok(expr),
+                    }
+                };
+                let (left, op, right) =
+                    (convert(left)?, Operation(op).try_into()?, convert(right)?);
+                // polars 比较时左边的常量不会广播，'2021-08-01' < date 总是空的，把常量换到右边
+                let literal = |expr: &Expr| matches!(expr, Expr::Literal(_));
+                // 整数除以 0 在 polars 里会 panic，SQL 的结果是 NULL
+                if matches!(op, Operator::Divide | Operator::Modulus)
+                    && matches!(right, Expr::Literal(LiteralValue::Int64(0)))
+                {
+                    return Ok(Expr::Literal(LiteralValue::Null));
+                }
+                match flip(op) {
+                    Some(flipped) if literal(&left) && !literal(&right) => Ok(Expr::BinaryExpr {
+                        left: Box::new(right),
+                        op: flipped,
+                        right: Box::new(left),
+                    }),
+                    _ => Ok(Expr::BinaryExpr {
+                        left: Box::new(left),
+                        op,
+                        right: Box::new(right),
+                    }),
+                }
+            }
+            SqlExpr::UnaryOp { op, expr } => {
+                let expr: Expr = Expression(expr).try_into()?;
+                match op {
+                    UnaryOperator::Plus => Ok(expr),
+                    UnaryOperator::Minus => match expr {
+                        Expr::Literal(LiteralValue::Int64(v)) => Ok(lit(-v)),
+                        Expr::Literal(LiteralValue::Float64(v)) => Ok(lit(-v)),
+                        expr => Ok(lit(0) - expr),
+                    },
+                    UnaryOperator::Not => Ok(expr.not()),
+                    v => Err(anyhow!("Operator {} is not supported", v)),
+                }
+            }
             SqlExpr::IsNull(expr) => Ok(Self::IsNull(Box::new(Expression(expr).try_into()?))),
             SqlExpr::IsNotNull(expr) => Ok(Self::IsNotNull(Box::new(Expression(expr).try_into()?))),
             SqlExpr::Identifier(id) => Ok(Self::Column(Arc::new(id.value))),
+            SqlExpr::CompoundIdentifier(ids) => Ok(Self::Column(Arc::new(compound_name(&ids)))),
             SqlExpr::Value(v) => Ok(Self::Literal(Value(v).try_into()?)),
+            SqlExpr::Nested(expr) => Expression(expr).try_into(),
+            SqlExpr::Function(f) => functions::to_expr(&f),
             v => Err(anyhow!("expr {:#?} is not support", v)),
         }
     }
@@ -127,6 +305,18 @@ impl TryFrom<Operation> for Operator {
     }
 }
 
+/// 交换比较运算两边之后的运算符，不是比较运算时返回 None
+fn flip(op: Operator) -> Option<Operator> {
+    match op {
+        Operator::Gt => Some(Operator::Lt),
+        Operator::Lt => Some(Operator::Gt),
+        Operator::GtEq => Some(Operator::LtEq),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Eq | Operator::NotEq => Some(op),
+        _ => None,
+    }
+}
+
 /// 把 SqlParser 的 SelectItem 转换成 DataFrame 的 Expr
 impl<'a> TryFrom<Projection<'a>> for Expr {
     type Error = anyhow::Error;
@@ -143,34 +333,271 @@ impl<'a> TryFrom<Projection<'a>> for Expr {
             )),
             SelectItem::QualifiedWildcard(v) => Ok(col(&v.to_string())),
             SelectItem::Wildcard => Ok(col("*")),
-            item => Err(anyhow!("projection {} not supported", item)),
+            // 函数之类的表达式，没有别名时用表达式本身作为列名
+            SelectItem::UnnamedExpr(expr) => {
+                let name = expr.to_string();
+                let expr: Expr = Expression(Box::new(expr.to_owned())).try_into()?;
+                Ok(expr.alias(&name))
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                let expr: Expr = Expression(Box::new(expr.to_owned())).try_into()?;
+                Ok(expr.alias(&alias.value))
+            }
         }
     }
 }
 
-/// 把 SqlParser 的 TableWithJoins 转换成 String
-impl<'a> TryFrom<Source<'a>> for &'a str {
+/// 把 SqlParser 的 TableWithJoins 转换成第一个数据源和后面的 join
+impl<'a> TryFrom<Source<'a>> for (Relation<'a>, Vec<Join<'a>>) {
     type Error = anyhow::Error;
 
     fn try_from(source: Source<'a>) -> Result<Self, Self::Error> {
         if source.0.len() != 1 {
-            return Err(anyhow!("We only support single data source at the moment"));
+            return Err(anyhow!(
+                "We only support single data source or JOIN ... ON at the moment"
+            ));
         }
 
         let table = &source.0[0];
-        if !table.joins.is_empty() {
-            return Err(anyhow!("We do not support joint data source at the moment"));
+        let relation = to_relation(&table.relation)?;
+        let mut joins = Vec::with_capacity(table.joins.len());
+        for join in &table.joins {
+            let (kind, constraint) = match &join.join_operator {
+                JoinOperator::Inner(c) => (JoinType::Inner, c),
+                JoinOperator::LeftOuter(c) => (JoinType::Left, c),
+                JoinOperator::FullOuter(c) => (JoinType::Outer, c),
+                v => return Err(anyhow!("Join {:?} is not supported", v)),
+            };
+            let on = match constraint {
+                JoinConstraint::On(expr) => {
+                    let mut conjuncts = Vec::new();
+                    split_conjuncts(expr, &mut conjuncts);
+                    conjuncts
+                        .into_iter()
+                        .map(|expr| match expr {
+                            SqlExpr::BinaryOp {
+                                left,
+                                op: SqlBinaryOperator::Eq,
+                                right,
+                            } => match (column_name(left), column_name(right)) {
+                                (Some(l), Some(r)) => Ok((l, r)),
+                                _ => Err(anyhow!("Join condition {} should compare columns", expr)),
+                            },
+                            v => Err(anyhow!("Join condition {} is not supported", v)),
+                        })
+                        .collect::<Result<_>>()?
+                }
+                _ => return Err(anyhow!("Join needs an ON condition")),
+            };
+            joins.push(Join {
+                relation: to_relation(&join.relation)?,
+                kind,
+                on,
+            });
+        }
+
+        // 同一个查询里有多个数据源时，需要用名字区分它们的列
+        if !joins.is_empty() {
+            for r in std::iter::once(&relation).chain(joins.iter().map(|j| &j.relation)) {
+                if r.alias.is_none() {
+                    return Err(anyhow!(
+                        "Data source {} in a join needs an alias, like {} AS t",
+                        r.source,
+                        r.source
+                    ));
+                }
+            }
+        }
+        Ok((relation, joins))
+    }
+}
+
+fn to_relation(table: &TableFactor) -> Result<Relation<'_>> {
+    let (name, alias, args, hints) = match table {
+        TableFactor::Table {
+            name,
+            alias,
+            args,
+            with_hints,
+        } => (name, alias, args, with_hints),
+        _ => return Err(anyhow!("We only support table")),
+    };
+    let mut reshape = None;
+    let source: &str = if args.is_empty() {
+        &name.0.first().unwrap().value
+    } else if let Some((source, v)) = Reshape::parse(name, args)? {
+        reshape = Some(v);
+        source
+    } else if is_read_url(name) {
+        // read_url('...') 可以读取任意的 url，不受 identifier 规则的限制
+        match args.as_slice() {
+            [FunctionArg::Unnamed(SqlExpr::Value(SqlValue::SingleQuotedString(url)))] => url,
+            _ => {
+                return Err(anyhow!(
+                    "read_url expects a single string argument, like read_url('https://...')"
+                ))
+            }
+        }
+    } else {
+        return Err(anyhow!("Table function {} is not supported", name));
+    };
+
+    // 没有别名时，注册的表名也可以用来引用列，比如 pets.name
+    let alias = match alias {
+        Some(v) => Some(v.name.value.clone()),
+        None if args.is_empty() && !source.contains("://") => Some(source.to_owned()),
+        None => None,
+    };
+    Ok(Relation {
+        source,
+        alias,
+        reshape,
+        sample: Sample::from_hints(hints),
+    })
+}
+
+/// 把用 AND 连接的条件拆开
+fn split_conjuncts<'a>(expr: &'a SqlExpr, out: &mut Vec<&'a SqlExpr>) {
+    match expr {
+        SqlExpr::BinaryOp {
+            left,
+            op: SqlBinaryOperator::And,
+            right,
+        } => {
+            split_conjuncts(left, out);
+            split_conjuncts(right, out);
+        }
+        SqlExpr::Nested(inner)
+            if matches!(
+                **inner,
+                SqlExpr::BinaryOp {
+                    op: SqlBinaryOperator::And,
+                    ..
+                }
+            ) =>
+        {
+            split_conjuncts(inner, out)
+        }
+        v => out.push(v),
+    }
+}
+
+/// 收集表达式用到的列名。遇到不认识的表达式返回 false，这时不能只读取部分列
+pub(crate) fn collect_columns(expr: &SqlExpr, out: &mut Vec<String>) -> bool {
+    match expr {
+        SqlExpr::Identifier(_) | SqlExpr::CompoundIdentifier(_) => {
+            out.extend(column_name(expr));
+            true
+        }
+        SqlExpr::Value(_) => true,
+        SqlExpr::BinaryOp { left, right, .. } => {
+            collect_columns(left, out) && collect_columns(right, out)
         }
+        SqlExpr::IsNull(expr)
+        | SqlExpr::IsNotNull(expr)
+        | SqlExpr::Nested(expr)
+        | SqlExpr::UnaryOp { expr, .. } => collect_columns(expr, out),
+        SqlExpr::Function(f) => f.args.iter().all(|arg| match arg {
+            FunctionArg::Unnamed(expr) | FunctionArg::Named { arg: expr, .. } => {
+                collect_columns(expr, out)
+            }
+        }),
+        _ => false,
+    }
+}
+
+/// 列名：a 或者 t.a
+pub(crate) fn column_name(expr: &SqlExpr) -> Option<String> {
+    match expr {
+        SqlExpr::Identifier(id) => Some(id.value.clone()),
+        SqlExpr::CompoundIdentifier(ids) => Some(compound_name(ids)),
+        _ => None,
+    }
+}
 
-        match &table.relation {
-            TableFactor::Table { name, .. } => Ok(&name.0.first().unwrap().value),
-            _ => Err(anyhow!("We only support table")),
+fn compound_name(ids: &[Ident]) -> String {
+    ids.iter()
+        .map(|v| v.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// 按三值逻辑逐行计算 AND / OR。polars 的布尔运算在只有一行并且是空值时会无限递归
+fn logical(left: Expr, right: Expr, and: bool) -> Expr {
+    let f = move |a: Series, b: Series| {
+        let (a, b) = (a.bool()?, b.bool()?);
+        // 字面量只有一行，扩展成和另一边一样长
+        let (a, b) = match (a.len(), b.len()) {
+            (1, len) if len != 1 => (a.expand_at_index(0, len), b.clone()),
+            (len, 1) if len != 1 => (a.clone(), b.expand_at_index(0, len)),
+            _ => (a.clone(), b.clone()),
+        };
+        let values: BooleanChunked = a
+            .into_iter()
+            .zip(&b)
+            .map(|v| match (v, and) {
+                ((Some(false), _) | (_, Some(false)), true) => Some(false),
+                ((Some(true), _) | (_, Some(true)), false) => Some(true),
+                ((Some(a), Some(_)), _) => Some(a),
+                _ => None,
+            })
+            .collect();
+        let mut s = values.into_series();
+        s.rename(a.name());
+        Ok(s)
+    };
+    map_binary(left, right, f, Some(Field::new("", DataType::Boolean)))
+}
+
+/// 只由列、常量、运算符组成的条件可以原样交给数据库执行。除法和取模在 SQLite 中
+/// 两边都是整数时结果也是整数，和 polars 不一致，不能下推
+pub(crate) fn is_portable(expr: &SqlExpr) -> bool {
+    match expr {
+        SqlExpr::BinaryOp { left, op, right } => {
+            is_portable_operator(op) && is_portable(left) && is_portable(right)
         }
+        SqlExpr::IsNull(expr)
+        | SqlExpr::IsNotNull(expr)
+        | SqlExpr::Nested(expr)
+        | SqlExpr::UnaryOp {
+            op: UnaryOperator::Plus | UnaryOperator::Minus | UnaryOperator::Not,
+            expr,
+        } => is_portable(expr),
+        SqlExpr::Identifier(_) | SqlExpr::Value(_) => true,
+        _ => false,
+    }
+}
+
+fn is_portable_operator(op: &SqlBinaryOperator) -> bool {
+    matches!(
+        op,
+        SqlBinaryOperator::Plus
+            | SqlBinaryOperator::Minus
+            | SqlBinaryOperator::Multiply
+            | SqlBinaryOperator::Gt
+            | SqlBinaryOperator::Lt
+            | SqlBinaryOperator::GtEq
+            | SqlBinaryOperator::LtEq
+            | SqlBinaryOperator::Eq
+            | SqlBinaryOperator::NotEq
+            | SqlBinaryOperator::And
+            | SqlBinaryOperator::Or
+    )
+}
+
+fn is_date_literal(expr: &SqlExpr) -> bool {
+    match expr {
+        SqlExpr::Value(SqlValue::SingleQuotedString(v)) => parse_datetime(v).is_some(),
+        _ => false,
     }
 }
 
+fn is_read_url(name: &ObjectName) -> bool {
+    name.0.len() == 1 && name.0[0].value.eq_ignore_ascii_case("read_url")
+}
+
 /// 把 SqlParser 的 Order by expr 转换成 (列名，排序方法)
-impl<'a> TryFrom<Order<'a>> for (String, bool) {
+impl<'a> TryFrom<Order<'a>> for (String, bool, Option<bool>) {
     type Error = anyhow::Error;
 
     fn try_from(order: Order<'a>) -> Result<Self, Self::Error> {
@@ -184,7 +611,7 @@ impl<'a> TryFrom<Order<'a>> for (String, bool) {
             }
         };
 
-        Ok((name, !order.0.asc.unwrap_or(true)))
+        Ok((name, !order.0.asc.unwrap_or(true), order.0.nulls_first))
     }
 }
 
@@ -217,7 +644,12 @@ impl TryFrom<Value> for LiteralValue {
 
     fn try_from(value: Value) -> Result<Self, Self::Error> {
         match value.0 {
-            SqlValue::Number(v, _) => Ok(LiteralValue::Float64(v.parse().unwrap())),
+            // 整数保持整数，和 SQL 一样整数相除时截断
+            SqlValue::Number(v, _) => match v.parse() {
+                Ok(v) => Ok(LiteralValue::Int64(v)),
+                Err(_) => Ok(LiteralValue::Float64(v.parse()?)),
+            },
+            SqlValue::SingleQuotedString(v) => Ok(LiteralValue::Utf8(v)),
             SqlValue::Boolean(v) => Ok(LiteralValue::Boolean(v)),
             SqlValue::Null => Ok(LiteralValue::Null),
             v => Err(anyhow!("Value {} is not supported", v)),
@@ -238,13 +670,85 @@ mod tests {
             "select a, b, c from {} where a=1 order by c desc limit 5 offset 10",
             url
         );
-        let statement = &Parser::parse_sql(&TyrDialect::default(), sql.as_ref()).unwrap()[0];
+        let statement = &Parser::parse_sql(&TyrDialect, sql.as_ref()).unwrap()[0];
         let sql: Sql = statement.try_into().unwrap();
         assert_eq!(sql.source, url);
         assert_eq!(sql.source, url);
         assert_eq!(sql.limit, Some(5));
         assert_eq!(sql.offset, Some(10));
-        assert_eq!(sql.order_by, vec![("c".into(), true)]);
+        assert_eq!(sql.order_by, vec![("c".into(), true, None)]);
         assert_eq!(sql.selection, vec![col("a"), col("b"), col("c")]);
     }
+
+    #[test]
+    fn pushdown_should_include_offset_in_limit() {
+        let sql = "SELECT a FROM t WHERE a = 1 ORDER BY a LIMIT 5 OFFSET 10";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        let pushdown = sql.pushdown();
+        assert_eq!(pushdown.filter.as_deref(), Some("a = 1"));
+        assert_eq!(pushdown.limit, Some(15));
+    }
+
+    #[test]
+    fn pushdown_needs_whole_condition() {
+        let sql = "SELECT a FROM t WHERE a > 1 AND strptime(b, '%Y') IS NULL ORDER BY a LIMIT 5";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        assert_eq!(sql.pushdown(), Pushdown::default());
+    }
+
+    #[test]
+    fn parse_negative_number_works() {
+        let parse = |condition: &str| {
+            let sql = format!("SELECT a FROM t WHERE {}", condition);
+            let statement = Parser::parse_sql(&TyrDialect, &sql).unwrap();
+            let sql: Sql = (&statement[0]).try_into().unwrap();
+            sql.condition
+        };
+        assert_eq!(parse("a > -5"), Some(col("a").gt(lit(-5i64))));
+        assert_eq!(parse("a > -0.5"), Some(col("a").gt(lit(-0.5))));
+        assert_eq!(parse("b < -a"), Some(col("b").lt(lit(0) - col("a"))));
+    }
+
+    #[test]
+    fn parse_quoted_source_works() {
+        let url = "https://abc.xyz/a%20b.csv?X-Amz-Signature=a+b~c,d";
+        for sql in [
+            format!("SELECT a FROM \"{}\"", url),
+            format!("SELECT a FROM `{}`", url),
+            format!("SELECT a FROM read_url('{}')", url),
+        ] {
+            let statement = &Parser::parse_sql(&TyrDialect, &sql).unwrap()[0];
+            let sql: Sql = statement.try_into().unwrap();
+            assert_eq!(sql.source, url);
+        }
+    }
+
+    #[test]
+    fn raw_condition_keeps_negative_numbers() {
+        let raw = |condition: &str| {
+            let sql = format!("SELECT a FROM t WHERE {}", condition);
+            let statement = Parser::parse_sql(&TyrDialect, &sql).unwrap();
+            let sql: Sql = (&statement[0]).try_into().unwrap();
+            sql.raw_condition
+        };
+        assert_eq!(raw("a > -5").as_deref(), Some("a > - 5"));
+        assert_eq!(raw("b < -(a)").as_deref(), Some("b < - (a)"));
+    }
+
+    #[test]
+    fn logical_should_use_three_valued_logic() {
+        let df = DataFrame::new(vec![Series::new("n", &[None::<i64>])]).unwrap();
+        let eval = |sql: &str| {
+            let statement = Parser::parse_sql(&TyrDialect, sql).unwrap();
+            let sql: Sql = (&statement[0]).try_into().unwrap();
+            let df = df.clone().lazy().filter(sql.condition.unwrap()).collect();
+            df.unwrap().height()
+        };
+        assert_eq!(eval("SELECT n FROM t WHERE n > 1 AND n < 5"), 0);
+        assert_eq!(eval("SELECT n FROM t WHERE n > 1 OR TRUE"), 1);
+        assert_eq!(eval("SELECT n FROM t WHERE NOT (n > 1 AND FALSE)"), 1);
+        assert_eq!(eval("SELECT n FROM t WHERE NOT (n > 1 AND TRUE)"), 0);
+    }
 }
@@ -0,0 +1,330 @@
+use crate::fetcher::Fetch;
+use crate::loader::{Cell, Load, Rows, SqlLoader};
+use crate::pushdown::Pushdown;
+use crate::DataSet;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::path::PathBuf;
+use tokio::task;
+use tokio_postgres::{NoTls, Row};
+use tracing::{info, warn};
+use url::Url;
+
+/// 是否是数据库类型的数据源：sqlite:///path/db.sqlite 或者 postgres://user@host/db
+pub(crate) fn is_database(source: &str) -> bool {
+    ["sqlite://", "postgres://", "postgresql://"]
+        .iter()
+        .any(|scheme| source.starts_with(scheme))
+}
+
+/// 从数据库读入数据。`?table=<name>` 读一张表，`?query=<sql>` 读一个查询的结果，
+/// 能下推的 WHERE / ORDER BY / LIMIT 会拼到发给数据库的 SQL 里
+pub(crate) async fn load(source: &str, pushdown: &Pushdown) -> Result<DataSet> {
+    let (url, relation) = parse(source)?;
+    let sql = pushdown.to_sql(&relation);
+    info!("querying database with: {}", sql);
+
+    let rows = match url.scheme() {
+        "sqlite" => {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow!("Invalid sqlite path: {}", source))?;
+            SqliteFetcher { path, sql }.fetch().await?
+        }
+        _ => {
+            let url = url.to_string();
+            PostgresFetcher { url, sql }.fetch().await?
+        }
+    };
+    SqlLoader(rows).load()
+}
+
+/// 发给数据库的 SQL，用于 EXPLAIN
+pub(crate) fn to_sql(source: &str, pushdown: &Pushdown) -> Result<String> {
+    Ok(pushdown.to_sql(&parse(source)?.1))
+}
+
+/// 拆出 table / query 参数，返回数据库连接的 url 和要查询的表或者子查询
+fn parse(source: &str) -> Result<(Url, String)> {
+    let mut url = Url::parse(source)?;
+    let mut table = None;
+    let mut query = None;
+    let mut params = Vec::new();
+    for (k, v) in url.query_pairs() {
+        match k.as_ref() {
+            "table" => table = Some(quote_identifier(&v)),
+            "query" => query = Some(format!("({}) AS t", v)),
+            _ => params.push((k.into_owned(), v.into_owned())),
+        }
+    }
+    let relation = table.or(query).ok_or_else(|| {
+        anyhow!(
+            "Database source {} needs ?table=<name> or ?query=<sql>",
+            source
+        )
+    })?;
+
+    // table / query 是给 queryer 的参数，剩下的参数留给数据库连接
+    url.set_query(None);
+    if !params.is_empty() {
+        url.query_pairs_mut().extend_pairs(params);
+    }
+    Ok((url, relation))
+}
+
+/// 表名或者列名加上双引号，sqlite 和 postgres 都支持
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+struct SqliteFetcher {
+    path: PathBuf,
+    sql: String,
+}
+
+struct PostgresFetcher {
+    url: String,
+    sql: String,
+}
+
+#[async_trait]
+impl Fetch for SqliteFetcher {
+    type Output = Rows;
+    type Error = anyhow::Error;
+
+    async fn fetch(&self) -> Result<Self::Output, Self::Error> {
+        let path = self.path.clone();
+        let sql = self.sql.clone();
+        // rusqlite 是同步的 API，放到专门的线程池里执行
+        task::spawn_blocking(move || {
+            let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let mut stmt = conn.prepare(&sql)?;
+            let columns: Vec<String> = stmt
+                .column_names()
+                .into_iter()
+                .map(|v| v.to_owned())
+                .collect();
+
+            let mut rows = Vec::new();
+            let mut result = stmt.query([])?;
+            while let Some(row) = result.next()? {
+                let mut values = Vec::with_capacity(columns.len());
+                for (i, column) in columns.iter().enumerate() {
+                    let value = match row.get_ref(i)? {
+                        ValueRef::Null => Cell::Null,
+                        ValueRef::Blob(_) => {
+                            return Err(anyhow!(
+                                "Column {} has BLOB values, which are not supported",
+                                column
+                            ))
+                        }
+                        ValueRef::Integer(v) => Cell::Int(v),
+                        ValueRef::Real(v) => Cell::Float(v),
+                        ValueRef::Text(v) => Cell::Text(String::from_utf8_lossy(v).into_owned()),
+                    };
+                    values.push(value);
+                }
+                rows.push(values);
+            }
+            Ok(Rows { columns, rows })
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl Fetch for PostgresFetcher {
+    type Output = Rows;
+    type Error = anyhow::Error;
+
+    async fn fetch(&self) -> Result<Self::Output, Self::Error> {
+        let (client, connection) = tokio_postgres::connect(&self.url, NoTls).await?;
+        // connection 负责和数据库通信，需要单独跑起来
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("postgres connection error: {}", e);
+            }
+        });
+
+        let mut stmt = client.prepare(&self.sql).await?;
+        // 不能直接读取的类型在外面再包一层 SELECT，转换成可以读取的类型
+        let exprs = stmt
+            .columns()
+            .iter()
+            .map(|c| pg_column(c.name(), c.type_().name()))
+            .collect::<Result<Vec<_>>>()?;
+        if exprs.iter().any(|(_, cast)| *cast) {
+            let exprs: Vec<&str> = exprs.iter().map(|(v, _)| v.as_str()).collect();
+            let sql = format!("SELECT {} FROM ({}) AS q", exprs.join(", "), self.sql);
+            info!("casting postgres columns with: {}", sql);
+            stmt = client.prepare(&sql).await?;
+        }
+        let columns = stmt.columns().iter().map(|c| c.name().to_owned()).collect();
+        let rows = client
+            .query(&stmt, &[])
+            .await?
+            .iter()
+            .map(|row| (0..row.len()).map(|i| pg_cell(row, i)).collect())
+            .collect::<Result<_>>()?;
+        Ok(Rows { columns, rows })
+    }
+}
+
+/// 读取一列时用的表达式，以及是否需要转换类型。numeric 转换成 float8；日期、时间和 uuid
+/// 之类的转换成字符串，其中日期和时间之后会被识别成 Date32 / Date64；其它类型返回错误
+fn pg_column(name: &str, type_name: &str) -> Result<(String, bool)> {
+    let column = quote_identifier(name);
+    let expr = match type_name {
+        "bool" | "int2" | "int4" | "int8" | "float4" | "float8" | "text" | "varchar" | "bpchar"
+        | "name" => return Ok((column, false)),
+        "numeric" => format!("{}::float8", column),
+        // 带时区的时间统一转换成 UTC
+        "timestamptz" => format!("({} AT TIME ZONE 'UTC')::text", column),
+        "date" | "timestamp" | "time" | "uuid" | "json" | "jsonb" | "inet" | "interval" => {
+            format!("{}::text", column)
+        }
+        v => {
+            return Err(anyhow!(
+                "Column {} has type {}, which is not supported. Cast it in ?query=<sql>",
+                name,
+                v
+            ))
+        }
+    };
+    Ok((format!("{} AS {}", expr, column), true))
+}
+
+/// 经过 pg_column 的转换之后，每一列都是可以直接读取的类型
+fn pg_cell(row: &Row, i: usize) -> Result<Cell> {
+    let cell = match row.columns()[i].type_().name() {
+        "bool" => row.try_get::<_, Option<bool>>(i)?.map(Cell::Bool),
+        "int2" => row
+            .try_get::<_, Option<i16>>(i)?
+            .map(|v| Cell::Int(v as i64)),
+        "int4" => row
+            .try_get::<_, Option<i32>>(i)?
+            .map(|v| Cell::Int(v as i64)),
+        "int8" => row.try_get::<_, Option<i64>>(i)?.map(Cell::Int),
+        "float4" => row
+            .try_get::<_, Option<f32>>(i)?
+            .map(|v| Cell::Float(v as f64)),
+        "float8" => row.try_get::<_, Option<f64>>(i)?.map(Cell::Float),
+        _ => row.try_get::<_, Option<String>>(i)?.map(Cell::Text),
+    };
+    Ok(cell.unwrap_or(Cell::Null))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+    use crate::testing::{temp_path, temp_source};
+    use polars::prelude::TakeRandom;
+
+    #[test]
+    fn pg_column_should_work() {
+        assert_eq!(pg_column("a", "int4").unwrap(), ("\"a\"".to_owned(), false));
+        assert_eq!(
+            pg_column("price", "numeric").unwrap(),
+            ("\"price\"::float8 AS \"price\"".to_owned(), true)
+        );
+        assert_eq!(
+            pg_column("day", "date").unwrap(),
+            ("\"day\"::text AS \"day\"".to_owned(), true)
+        );
+        assert!(pg_column("data", "bytea").is_err());
+    }
+
+    #[tokio::test]
+    async fn sqlite_source_should_work() {
+        let path = temp_path("database.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pets (name TEXT, age INTEGER, weight REAL);
+             INSERT INTO pets VALUES ('tom', 10, 4.5), ('jerry', 3, 0.2), ('spike', 8, NULL);",
+        )
+        .unwrap();
+
+        let sql = format!(
+            "SELECT name, weight FROM sqlite://{}?table=pets WHERE age > 5 ORDER BY age DESC LIMIT 1",
+            path.display()
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("tom")
+        );
+        assert_eq!(
+            ds.column("weight").unwrap().f64().unwrap().get(0),
+            Some(4.5)
+        );
+
+        // WHERE 中有不能下推的函数时，LIMIT 也不能下推，否则数据库只返回 jerry
+        let sql = format!(
+            "SELECT name FROM sqlite://{}?table=pets \
+            WHERE strptime(name, '%Y') IS NULL AND age > 5 ORDER BY age LIMIT 1",
+            path.display()
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("spike")
+        );
+
+        let sql = format!(
+            "SELECT name FROM \"sqlite://{}?query=SELECT name, age FROM pets WHERE age < 5\"",
+            path.display()
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+
+        // join 时只涉及一个数据源的条件和用到的列会下推到各自的数据库
+        let source = format!("sqlite://{}?table=pets", path.display());
+        let sql = format!(
+            "SELECT p.name, q.weight FROM {} AS p JOIN {} AS q ON p.name = q.name WHERE p.age > 5",
+            source, source
+        );
+        let ds = query(&sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        let plan = query(format!("EXPLAIN {}", sql)).await.unwrap();
+        let details = plan.column("detail").unwrap().utf8().unwrap();
+        assert_eq!(
+            details.get(0),
+            Some("pushed into database: SELECT \"age\", \"name\" FROM \"pets\" WHERE (age > 5)")
+        );
+        assert_eq!(
+            details.get(1),
+            Some("pushed into database: SELECT \"name\", \"weight\" FROM \"pets\"")
+        );
+
+        // BLOB 没法转换，返回错误而不是当作空值
+        conn.execute_batch("CREATE TABLE files (data BLOB); INSERT INTO files VALUES (x'00ff');")
+            .unwrap();
+        let sql = format!("SELECT * FROM sqlite://{}?table=files", path.display());
+        assert!(query(sql).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sqlite_should_match_csv() {
+        let path = temp_path("arith.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE pets (name TEXT, age INTEGER);
+             INSERT INTO pets VALUES ('tom', 10), ('jerry', 3), ('spike', 8);",
+        )
+        .unwrap();
+        let csv = temp_source("arith.csv", "name,age\ntom,10\njerry,3\nspike,8\n");
+        let sqlite = format!("sqlite://{}?table=pets", path.display());
+
+        // SQLite 中 10 / 4 是 2，整数除法的条件不能下推
+        for condition in ["age / 4 > 2", "age % 4 = 2", "age * 2 - 1 > 15"] {
+            let sql = |source: &str| format!("SELECT name FROM {} WHERE {}", source, condition);
+            let expected = query(sql(&csv)).await.unwrap();
+            let ds = query(sql(&sqlite)).await.unwrap();
+            assert_eq!(ds.height(), expected.height(), "{}", condition);
+        }
+    }
+}
@@ -6,15 +6,20 @@ pub struct TyrDialect;
 // 创建自己的 sql 方言。TyrDialect 支持 identifier 可以是简单的 url
 impl Dialect for TyrDialect {
     fn is_identifier_start(&self, ch: char) -> bool {
-        ('a'..='z').contains(&ch) || ('A'..='Z').contains(&ch) || ch == '_'
+        ch.is_ascii_alphabetic() || ch == '_'
     }
 
-    // identifier 可以有 ':', '/', '?', '&', '='
+    // identifier 可以有 ':', '/', '?', '&', '=', '#'，'#' 之后是数据源的参数，比如 zip 里的 entry；
+    // '@' 用于 postgres://user@host/db 这样的数据库地址。'%'、'+'、',' 等字符会和运算符、
+    // 逗号冲突，包含它们的 url 需要用引号括起来，或者使用 read_url('...')
     fn is_identifier_part(&self, ch: char) -> bool {
-        ('a'..='z').contains(&ch)
-            || ('A'..='Z').contains(&ch)
-            || ('0'..='9').contains(&ch)
-            || [':', '/', '?', '&', '=', '-', '_', '.'].contains(&ch)
+        ch.is_ascii_alphanumeric()
+            || [':', '/', '?', '&', '=', '-', '_', '.', '#', '@', '~'].contains(&ch)
+    }
+
+    // 除了标准的双引号，也支持 MySQL 风格的反引号："https://..." 或者 `https://...`
+    fn is_delimited_identifier_start(&self, ch: char) -> bool {
+        ch == '"' || ch == '`'
     }
 }
 
@@ -36,6 +41,6 @@ mod tests {
 
     #[test]
     fn it_works() {
-        assert!(Parser::parse_sql(&TyrDialect::default(), &example_sql()).is_ok());
+        assert!(Parser::parse_sql(&TyrDialect, &example_sql()).is_ok());
     }
 }
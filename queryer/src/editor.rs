@@ -0,0 +1,315 @@
+use crate::command::Command;
+use crate::convert::Sql;
+use crate::{ErrorKind, TyrDialect};
+use sqlparser::dialect::Dialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer, TokenizerError};
+
+/// 补全时提示的关键字
+const KEYWORDS: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "AND",
+    "OR",
+    "NOT",
+    "IS",
+    "NULL",
+    "TRUE",
+    "FALSE",
+    "AS",
+    "ORDER BY",
+    "ASC",
+    "DESC",
+    "LIMIT",
+    "OFFSET",
+    "DESCRIBE",
+    "SUMMARIZE",
+    "SHOW COLUMNS FROM",
+];
+
+/// 这些关键字后面跟的是表名或者数据源。DESC 只有在开头时才是 DESCRIBE，见 complete
+const TABLE_KEYWORDS: &[&str] = &["FROM", "JOIN", "DESCRIBE", "SUMMARIZE"];
+
+/// SQL 中的一段位置，start 和 end 都是字节偏移，左闭右开
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// 诊断的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// SQL 语法错误
+    Syntax,
+    /// 语法正确，但 queryer 还不支持
+    Unsupported,
+}
+
+/// 校验 SQL 得到的一条诊断信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub span: Span,
+}
+
+/// 补全候选项的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Table,
+    Column,
+}
+
+/// 一个补全候选项，选中后用 label 替换 replace 这段文本
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub replace: Span,
+}
+
+/// 补全时可以提示的表名和列名
+#[derive(Debug, Default, Clone)]
+pub struct Catalog {
+    pub tables: Vec<String>,
+    pub columns: Vec<String>,
+}
+
+/// 校验 SQL 但不执行：返回语法错误，以及 queryer 暂不支持的特性
+pub fn validate(sql: &str) -> Vec<Diagnostic> {
+    let tokens = match tokenize(sql) {
+        Ok(v) => v,
+        Err(e) => {
+            let start = offset_of(sql, e.line, e.col);
+            return vec![Diagnostic {
+                kind: DiagnosticKind::Syntax,
+                message: e.message,
+                span: Span {
+                    start,
+                    end: sql.len(),
+                },
+            }];
+        }
+    };
+
+    let whole = Span {
+        start: sql.len() - sql.trim_start().len(),
+        end: sql.trim_end().len(),
+    };
+    let statement = match Command::parse(sql) {
+        Ok(Command::Select(v)) | Ok(Command::Summarize(v)) | Ok(Command::Explain(v)) => v,
+        Ok(_) => return Vec::new(),
+        Err(e) => {
+            let (kind, span) = match ErrorKind::of(&e) {
+                ErrorKind::Syntax => (
+                    DiagnosticKind::Syntax,
+                    locate_error(&tokens).unwrap_or(Span {
+                        start: whole.end,
+                        end: whole.end,
+                    }),
+                ),
+                _ => (DiagnosticKind::Unsupported, whole),
+            };
+            return vec![Diagnostic {
+                kind,
+                message: e.to_string(),
+                span,
+            }];
+        }
+    };
+
+    // 用 convert.rs 中的转换规则检查是否有不支持的特性
+    match Sql::try_from(&statement) {
+        Ok(_) => Vec::new(),
+        Err(e) => vec![Diagnostic {
+            kind: DiagnosticKind::Unsupported,
+            message: e.to_string(),
+            span: whole,
+        }],
+    }
+}
+
+/// 根据光标位置（字节偏移）给出补全候选：FROM 之后提示表名，其它位置提示列名和关键字
+pub fn complete(sql: &str, cursor: usize, catalog: &Catalog) -> Vec<Completion> {
+    let mut cursor = cursor.min(sql.len());
+    while !sql.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+
+    let dialect = TyrDialect;
+    let before = &sql[..cursor];
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| dialect.is_identifier_part(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(cursor);
+    let prefix = before[start..].to_lowercase();
+    let replace = Span { start, end: cursor };
+
+    let words: Vec<&str> = before[..start].split_whitespace().collect();
+    let previous = words.last().copied().unwrap_or_default().to_uppercase();
+    // 开头的 DESC 是 DESCRIBE 的缩写，ORDER BY 中的 DESC 是降序
+    let table = match previous.as_str() {
+        "DESC" => words.len() == 1,
+        v => TABLE_KEYWORDS.contains(&v),
+    };
+    let candidates: Vec<(&str, CompletionKind)> = if table {
+        catalog
+            .tables
+            .iter()
+            .map(|v| (v.as_str(), CompletionKind::Table))
+            .collect()
+    } else {
+        catalog
+            .columns
+            .iter()
+            .map(|v| (v.as_str(), CompletionKind::Column))
+            .chain(KEYWORDS.iter().map(|v| (*v, CompletionKind::Keyword)))
+            .collect()
+    };
+
+    candidates
+        .into_iter()
+        .filter(|(label, _)| label.to_lowercase().starts_with(&prefix))
+        .map(|(label, kind)| Completion {
+            label: label.to_owned(),
+            kind,
+            replace,
+        })
+        .collect()
+}
+
+/// 分词，并且记录每个 token 在原始 SQL 中的位置
+fn tokenize(sql: &str) -> Result<Vec<(Token, Span)>, TokenizerError> {
+    let dialect = TyrDialect;
+    let tokens = Tokenizer::new(&dialect, sql).tokenize()?;
+    let mut offset = 0;
+    let result = tokens
+        .into_iter()
+        .map(|token| {
+            let text = token.to_string();
+            let start = sql[offset..]
+                .find(&text)
+                .map(|i| offset + i)
+                .unwrap_or(offset);
+            let end = (start + text.len()).min(sql.len());
+            offset = end;
+            (token, Span { start, end })
+        })
+        .collect();
+    Ok(result)
+}
+
+/// sqlparser 的错误里没有位置信息，只有 `found: <token>`。解析一次，
+/// 在解析器停下的位置前后找到这个 token；语句没写完（found: EOF）时返回 None
+fn locate_error(tokens: &[(Token, Span)]) -> Option<Span> {
+    let dialect = TyrDialect;
+    let mut parser = Parser::new(tokens.iter().map(|(t, _)| t.clone()).collect(), &dialect);
+    let found = match parser.parse_statement() {
+        // 和 Parser::parse_sql 一样，语句之后只能是分号或者结束
+        Ok(_) => match parser.peek_token() {
+            Token::EOF | Token::SemiColon => return None,
+            token => token.to_string(),
+        },
+        Err(e) => e.to_string().rsplit_once("found: ")?.1.to_owned(),
+    };
+
+    // 出错的 token 可能已经被解析器取出，也可能还没有
+    let mut remaining = 0;
+    while parser.next_token_no_skip().is_some() {
+        remaining += 1;
+    }
+    let index = tokens.len() - remaining;
+    let is_found = |(token, _): &&(Token, Span)| {
+        !matches!(token, Token::Whitespace(_)) && token.to_string() == found
+    };
+    let not_whitespace = |(token, _): &&(Token, Span)| !matches!(token, Token::Whitespace(_));
+    tokens[index..]
+        .iter()
+        .find(not_whitespace)
+        .filter(is_found)
+        .or_else(|| {
+            tokens[..index]
+                .iter()
+                .rev()
+                .find(not_whitespace)
+                .filter(is_found)
+        })
+        .map(|(_, span)| *span)
+}
+
+/// 把行号和列号（从 1 开始）转换成字节偏移
+fn offset_of(sql: &str, line: u64, col: u64) -> usize {
+    let line_start: usize = sql
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1) as usize)
+        .map(|l| l.len())
+        .sum();
+    sql[line_start..]
+        .char_indices()
+        .nth(col.saturating_sub(1) as usize)
+        .map(|(i, _)| line_start + i)
+        .unwrap_or(sql.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_should_report_syntax_error() {
+        let sql = "SELECT a FROM t WHERE a > ORDER BY b";
+        let diagnostics = validate(sql);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::Syntax);
+        let span = diagnostics[0].span;
+        // sqlparser 把 ORDER 当成了列名，在 BY 处报错
+        assert_eq!(&sql[span.start..span.end], "BY");
+
+        let sql = "SELECT a\nFROM t\nORDER b";
+        let span = validate(sql)[0].span;
+        assert_eq!(span, Span { start: 16, end: 21 });
+
+        // 没写完的语句标在末尾
+        let sql = "SELECT a FROM t WHERE";
+        assert_eq!(validate(sql)[0].span, Span { start: 21, end: 21 });
+    }
+
+    #[test]
+    fn validate_should_report_unsupported_feature() {
+        let diagnostics = validate("SELECT a FROM t1, t2");
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::Unsupported);
+
+        assert!(validate("SELECT a FROM t WHERE a > 1").is_empty());
+    }
+
+    #[test]
+    fn complete_should_work() {
+        let catalog = Catalog {
+            tables: vec!["owid".into(), "cases".into()],
+            columns: vec!["location".into(), "new_cases".into()],
+        };
+
+        let sql = "SELECT loc FROM o";
+        let result = complete(sql, sql.len(), &catalog);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].label, "owid");
+        assert_eq!(result[0].kind, CompletionKind::Table);
+        assert_eq!(result[0].replace, Span { start: 16, end: 17 });
+
+        let result = complete(sql, 10, &catalog);
+        assert_eq!(result[0].label, "location");
+        assert_eq!(result[0].kind, CompletionKind::Column);
+
+        let result = complete("DESC ", 5, &catalog);
+        assert_eq!(result[0].kind, CompletionKind::Table);
+        let sql = "SELECT location FROM owid ORDER BY location DESC ";
+        let result = complete(sql, sql.len(), &catalog);
+        assert!(result.iter().all(|v| v.kind != CompletionKind::Table));
+    }
+}
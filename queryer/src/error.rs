@@ -0,0 +1,61 @@
+use crate::LimitError;
+use polars::prelude::PolarsError;
+use sqlparser::parser::ParserError;
+
+/// 错误的分类。queryer 内部统一使用 anyhow，各语言的绑定可以根据分类映射成自己的异常类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// SQL 语法错误
+    Syntax,
+    /// 获取数据源失败，比如文件不存在、网络错误、数据库查询失败
+    Fetch,
+    /// 加载或者计算数据失败
+    Data,
+    /// 超过了 QueryOptions 中的限制，比如超时、数据源过大
+    Limit,
+    /// 其它错误，比如使用了暂不支持的 SQL 特性
+    Other,
+}
+
+impl ErrorKind {
+    /// 沿着错误链找到第一个能识别的错误
+    pub fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if cause.is::<LimitError>() {
+                return ErrorKind::Limit;
+            }
+            if cause.is::<ParserError>() {
+                return ErrorKind::Syntax;
+            }
+            if cause.is::<std::io::Error>()
+                || cause.is::<reqwest::Error>()
+                || cause.is::<rusqlite::Error>()
+                || cause.is::<tokio_postgres::Error>()
+            {
+                return ErrorKind::Fetch;
+            }
+            if cause.is::<PolarsError>() {
+                return ErrorKind::Data;
+            }
+        }
+        ErrorKind::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+
+    #[tokio::test]
+    async fn error_kind_should_work() {
+        let err = query("SELECT FROM WHERE").await.unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Syntax);
+
+        let err = query("SELECT a FROM file:///not/exist.csv")
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Fetch);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::loader::{Cell, Load, Rows, SqlLoader};
+use crate::DataSet;
+use anyhow::{anyhow, Result};
+use calamine::{DataType, Ods, Range, Reader, Xls, Xlsx};
+use chrono::Timelike;
+use std::io::{Cursor, Read};
+
+/// 支持的电子表格格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetFormat {
+    Xlsx,
+    Xls,
+    Ods,
+}
+
+impl SheetFormat {
+    /// 根据 magic bytes 判断：xls 是 OLE2 复合文档，xlsx 和 ods 是特定结构的 zip
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1, ..] => Some(SheetFormat::Xls),
+            [b'P', b'K', 0x03, 0x04, ..] => {
+                let mut archive = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+                if archive.by_name("xl/workbook.xml").is_ok() {
+                    return Some(SheetFormat::Xlsx);
+                }
+                let mut mimetype = String::new();
+                archive
+                    .by_name("mimetype")
+                    .ok()?
+                    .read_to_string(&mut mimetype)
+                    .ok()?;
+                match mimetype.trim() {
+                    "application/vnd.oasis.opendocument.spreadsheet" => Some(SheetFormat::Ods),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 读取电子表格中的一个 sheet。`#sheet=Q1` 指定 sheet，默认是第一个；
+/// `#range=A1:F200` 指定单元格区域，默认是整个 sheet。区域的第一行是列名
+#[derive(Debug)]
+pub struct ExcelLoader {
+    pub(crate) data: Vec<u8>,
+    pub(crate) format: SheetFormat,
+    pub(crate) sheet: Option<String>,
+    pub(crate) range: Option<String>,
+}
+
+impl Load for ExcelLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let data = Cursor::new(self.data);
+        let sheet = self.sheet.as_deref();
+        let range = match self.format {
+            SheetFormat::Xlsx => read_sheet(Xlsx::new(data)?, sheet)?,
+            SheetFormat::Xls => read_sheet(Xls::new(data)?, sheet)?,
+            SheetFormat::Ods => read_sheet(Ods::new(data)?, sheet)?,
+        };
+        let range = match &self.range {
+            Some(v) => {
+                let (start, end) = parse_range(v)?;
+                // 先截到表格实际的范围，否则 calamine 会按 A1:XFD1048576 这样的范围分配单元格
+                match range.end() {
+                    Some((rows, cols)) if start.0 <= rows && start.1 <= cols => {
+                        range.range(start, (end.0.min(rows), end.1.min(cols)))
+                    }
+                    _ => Range::empty(),
+                }
+            }
+            None => range,
+        };
+        // 每一列的类型和数据库的结果一样，根据这一列的值推断
+        SqlLoader(to_rows(&range)).load()
+    }
+}
+
+fn read_sheet<R, RS>(mut workbook: R, sheet: Option<&str>) -> Result<Range<DataType>>
+where
+    R: Reader<RS>,
+    R::Error: std::error::Error + Send + Sync + 'static,
+    RS: std::io::Read + std::io::Seek,
+{
+    let names = workbook.sheet_names().to_owned();
+    let name = match sheet {
+        Some(v) => v.to_owned(),
+        None => names
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow!("Workbook has no sheet"))?,
+    };
+    match workbook.worksheet_range(&name) {
+        Some(range) => Ok(range?),
+        None => Err(anyhow!(
+            "Sheet {} not found, available sheets: {}",
+            name,
+            names.join(", ")
+        )),
+    }
+}
+
+/// 第一行是列名，空的列名用 column_<n> 代替
+fn to_rows(range: &Range<DataType>) -> Rows {
+    let mut rows = range.rows();
+    let columns = match rows.next() {
+        Some(header) => header
+            .iter()
+            .enumerate()
+            .map(|(i, v)| match v {
+                DataType::Empty => format!("column_{}", i + 1),
+                v => v.to_string(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let rows = rows.map(|row| row.iter().map(to_cell).collect()).collect();
+    Rows { columns, rows }
+}
+
+/// 电子表格里的数字都是浮点数，没有小数部分的当作整数，这样整数列能推断成 i64
+fn to_cell(value: &DataType) -> Cell {
+    match value {
+        DataType::Empty | DataType::Error(_) => Cell::Null,
+        DataType::Bool(v) => Cell::Bool(*v),
+        DataType::Int(v) => Cell::Int(*v),
+        DataType::Float(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => Cell::Int(*v as i64),
+        DataType::Float(v) => Cell::Float(*v),
+        DataType::String(v) => Cell::Text(v.to_owned()),
+        // 日期先转换成字符串，SqlLoader 再把都是日期的列转换成 Date32 / Date64
+        DataType::DateTime(_) => match value.as_datetime() {
+            Some(dt) if dt.num_seconds_from_midnight() == 0 => {
+                Cell::Text(dt.format("%Y-%m-%d").to_string())
+            }
+            Some(dt) => Cell::Text(dt.format("%Y-%m-%d %H:%M:%S").to_string()),
+            None => Cell::Null,
+        },
+    }
+}
+
+/// 电子表格最大的行数和列数（XFD1048576）
+const MAX_ROWS: u32 = 1_048_576;
+const MAX_COLS: u32 = 16_384;
+
+/// 把 `A1:F200` 解析成从 0 开始的 (行, 列) 坐标
+fn parse_range(range: &str) -> Result<((u32, u32), (u32, u32))> {
+    let (start, end) = range
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid range {}, expect something like A1:F200", range))?;
+    let (start, end) = (parse_cell(start)?, parse_cell(end)?);
+    if start.0 > end.0 || start.1 > end.1 {
+        return Err(anyhow!(
+            "Invalid range {}, the first cell should be the top left one",
+            range
+        ));
+    }
+    Ok((start, end))
+}
+
+fn parse_cell(cell: &str) -> Result<(u32, u32)> {
+    let cell = cell.trim().to_uppercase();
+    let invalid = || {
+        anyhow!(
+            "Invalid cell {}, expect something between A1 and XFD1048576",
+            cell
+        )
+    };
+    let split = cell
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (letters, digits) = cell.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(invalid());
+    }
+    let col = letters
+        .chars()
+        .try_fold(0u32, |acc, c| {
+            acc.checked_mul(26)?.checked_add(c as u32 - 'A' as u32 + 1)
+        })
+        .filter(|v| *v <= MAX_COLS)
+        .ok_or_else(invalid)?;
+    let row = digits
+        .parse::<u32>()
+        .ok()
+        .filter(|v| (1..=MAX_ROWS).contains(v))
+        .ok_or_else(invalid)?;
+    Ok((row - 1, col - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::{DataType as PolarsType, TakeRandom};
+    use std::io::Write;
+
+    /// 手工拼一个最小的 xlsx，单元格都用 inline string 或数字
+    fn xlsx(rows: &[&[&str]]) -> Vec<u8> {
+        let sheet_data: String = rows
+            .iter()
+            .enumerate()
+            .map(|(r, row)| {
+                let cells: String = row
+                    .iter()
+                    .enumerate()
+                    .map(|(c, v)| {
+                        let pos = format!("{}{}", (b'A' + c as u8) as char, r + 1);
+                        match v.parse::<f64>() {
+                            Ok(_) => format!(r#"<c r="{}"><v>{}</v></c>"#, pos, v),
+                            Err(_) => {
+                                format!(r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#, pos, v)
+                            }
+                        }
+                    })
+                    .collect();
+                format!(r#"<row r="{}">{}</row>"#, r + 1, cells)
+            })
+            .collect();
+        let files = [
+            (
+                "[Content_Types].xml",
+                r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#.to_owned(),
+            ),
+            (
+                "xl/workbook.xml",
+                r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Q1" sheetId="1" r:id="rId1"/></sheets></workbook>"#.to_owned(),
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#.to_owned(),
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                format!(
+                    r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#,
+                    sheet_data
+                ),
+            ),
+        ];
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(name, Default::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn parse_range_should_work() {
+        assert_eq!(parse_range("A1:F200").unwrap(), ((0, 0), (199, 5)));
+        assert_eq!(parse_cell("AA10").unwrap(), (9, 26));
+        assert_eq!(parse_cell("XFD1048576").unwrap(), (1_048_575, 16_383));
+        assert!(parse_range("A1").is_err());
+        assert!(parse_range("F200:A1").is_err());
+        assert!(parse_range("B1:A5").is_err());
+        assert!(parse_cell("10").is_err());
+        assert!(parse_cell("A0").is_err());
+        assert!(parse_cell("XFE1").is_err());
+        assert!(parse_cell("A1048577").is_err());
+        assert!(parse_cell("ZZZZZZZZZZZZZZ1").is_err());
+    }
+
+    #[test]
+    fn excel_loader_should_work() {
+        let data = xlsx(&[
+            &["title", "", ""],
+            &["name", "age", "weight"],
+            &["tom", "10", "4.5"],
+            &["jerry", "3", "0.2"],
+        ]);
+        assert_eq!(SheetFormat::detect(&data), Some(SheetFormat::Xlsx));
+
+        let load = |range: &str| {
+            let loader = ExcelLoader {
+                data: data.clone(),
+                format: SheetFormat::Xlsx,
+                sheet: Some("Q1".into()),
+                range: Some(range.into()),
+            };
+            loader.load().unwrap()
+        };
+        let ds = load("A2:C4");
+        assert_eq!(ds.get_column_names(), ["name", "age", "weight"]);
+        assert_eq!(ds.column("age").unwrap().i64().unwrap().get(0), Some(10));
+        assert_eq!(
+            ds.column("weight").unwrap().f64().unwrap().get(1),
+            Some(0.2)
+        );
+
+        // 超出表格的部分不会分配单元格
+        let ds = load("A2:XFD1048576");
+        assert_eq!(ds.get_column_names(), ["name", "age", "weight"]);
+        assert_eq!(ds.height(), 2);
+        assert_eq!(load("Z100:Z200").width(), 0);
+    }
+
+    #[test]
+    fn date_cells_should_be_dates() {
+        // 44409 是 2021-08-01，小数部分是一天中的时间
+        let rows = Rows {
+            columns: vec!["day".into(), "time".into()],
+            rows: vec![
+                vec![
+                    to_cell(&DataType::DateTime(44409.0)),
+                    to_cell(&DataType::DateTime(44409.5)),
+                ],
+                vec![
+                    to_cell(&DataType::Empty),
+                    to_cell(&DataType::DateTime(44410.0)),
+                ],
+            ],
+        };
+        let ds = SqlLoader(rows).load().unwrap();
+        assert_eq!(ds.column("day").unwrap().dtype(), &PolarsType::Date32);
+        assert_eq!(ds.column("time").unwrap().dtype(), &PolarsType::Date64);
+    }
+}
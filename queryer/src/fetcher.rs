@@ -1,44 +1,189 @@
+use crate::compression::decompress;
+use crate::options::{LimitError, QueryOptions};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use percent_encoding::percent_decode_str;
+use reqwest::redirect;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use tokio::fs;
 
 // Rust 的 async trait 还没有稳定，可以用async_trait 宏
 #[async_trait]
 pub trait Fetch {
+    type Output;
     type Error;
-    async fn fetch(&self) -> Result<String, Self::Error>;
+    async fn fetch(&self) -> Result<Self::Output, Self::Error>;
 }
 
-/// 从文件源或者 HTTP 源中获取数据，返回字符串
-pub async fn retrieve_data(source: impl AsRef<str>) -> Result<String> {
-    let name = source.as_ref();
-    match &name[..4] {
-        // 包括 http / https
-        "http" => UrlFetcher(name).fetch().await,
+/// 从文件源或者 HTTP 源中获取数据，如果数据是压缩过的会自动解压，返回原始的字节。
+/// 数据源的地址和大小受 options 的限制
+pub async fn retrieve_data(source: impl AsRef<str>, options: &QueryOptions) -> Result<Vec<u8>> {
+    let (name, fragment) = split_fragment(source.as_ref());
+    options.check_source(name)?;
+    let data = match name.split_once("://").map(|(scheme, _)| scheme) {
+        Some("http") | Some("https") => UrlFetcher(name, options).fetch().await?,
         // 处理 file://<filename>
-        "file" => FileFetcher(name).fetch().await,
-        _ => Err(anyhow!("We only support http/https/file at the moment")),
+        Some("file") => FileFetcher(name, options).fetch().await?,
+        _ => return Err(anyhow!("We only support http/https/file at the moment")),
+    };
+    let entry = fragment.get("entry").map(|v| v.as_ref());
+    let data = decompress(name, data, entry, options.max_source_bytes)?;
+    // 解压之后的数据也不能超过限制，decompress 最多多读一个字节
+    options.check_bytes(data.len() as u64)?;
+    Ok(data)
+}
+
+/// 把 `file:///data.zip#entry=a.csv` 拆成地址和 `#` 之后的参数，参数的值会做 percent-decoding
+pub(crate) fn split_fragment(source: &str) -> (&str, HashMap<&str, Cow<'_, str>>) {
+    match source.split_once('#') {
+        Some((name, fragment)) => {
+            let params = fragment
+                .split('&')
+                .filter_map(|kv| kv.split_once('='))
+                .map(|(k, v)| (k, percent_decode_str(v).decode_utf8_lossy()))
+                .collect();
+            (name, params)
+        }
+        None => (source, HashMap::new()),
+    }
+}
+
+/// file:// 数据源对应的文件路径。文件名里的空格等字符可能是 percent-encoded 的，比如 file:///my%20data.csv
+pub(crate) fn file_path(source: &str) -> Option<String> {
+    let path = source.strip_prefix("file://")?;
+    Some(percent_decode_str(path).decode_utf8_lossy().into_owned())
+}
+
+/// 和 reqwest 默认的重定向策略一样，最多跟随 10 次
+const MAX_REDIRECTS: usize = 10;
+
+/// 重定向被拒绝时 reqwest 会把 LimitError 包起来，取出来方便调用者判断
+fn limit_error(err: &reqwest::Error) -> Option<LimitError> {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(limit) = e.downcast_ref::<LimitError>() {
+            return Some(limit.clone());
+        }
+        source = e.source();
     }
+    None
 }
 
-struct FileFetcher<'a>(pub(crate) &'a str);
+struct FileFetcher<'a>(pub(crate) &'a str, pub(crate) &'a QueryOptions);
 
-struct UrlFetcher<'a>(pub(crate) &'a str);
+struct UrlFetcher<'a>(pub(crate) &'a str, pub(crate) &'a QueryOptions);
 
 #[async_trait]
 impl<'a> Fetch for FileFetcher<'a> {
+    type Output = Vec<u8>;
     type Error = anyhow::Error;
 
-    async fn fetch(&self) -> Result<String, Self::Error> {
-        Ok(fs::read_to_string(&self.0[7..]).await?)
+    async fn fetch(&self) -> Result<Self::Output, Self::Error> {
+        let path = file_path(self.0).ok_or_else(|| anyhow!("Invalid file path: {}", self.0))?;
+        self.1.check_bytes(fs::metadata(&path).await?.len())?;
+        Ok(fs::read(&path).await?)
     }
 }
 
 #[async_trait]
 impl<'a> Fetch for UrlFetcher<'a> {
+    type Output = Vec<u8>;
     type Error = anyhow::Error;
 
-    async fn fetch(&self) -> Result<String, Self::Error> {
-        Ok(reqwest::get(self.0).await?.text().await?)
+    async fn fetch(&self) -> Result<Self::Output, Self::Error> {
+        // 每次重定向都要重新检查地址，否则允许的主机可以把请求转到任意地址
+        let options = self.1.clone();
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(e) = options.check_source(attempt.url().as_str()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = reqwest::Client::builder().redirect(policy).build()?;
+        let mut res = client
+            .get(self.0)
+            .send()
+            .await
+            .map_err(|e| match limit_error(&e) {
+                Some(limit) => anyhow!(limit),
+                None => e.into(),
+            })?;
+        if let Some(len) = res.content_length() {
+            self.1.check_bytes(len)?;
+        }
+        // 没有 Content-Length 或者服务器不老实的时候，边下载边检查
+        let mut data = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            data.extend_from_slice(&chunk);
+            self.1.check_bytes(data.len() as u64)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn retrieve_percent_encoded_file_should_work() {
+        let path = temp_path("my data.csv");
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+
+        let source = format!("file://{}", path.display()).replace(' ', "%20");
+        let options = QueryOptions::default();
+        let data = retrieve_data(&source, &options).await.unwrap();
+        assert_eq!(data, b"a,b\n1,2\n");
+
+        assert!(retrieve_data("s3", &options).await.is_err());
+
+        let options = QueryOptions {
+            max_source_bytes: Some(4),
+            ..Default::default()
+        };
+        let err = retrieve_data(&source, &options).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitError>(),
+            Some(&LimitError::SourceTooLarge { limit: 4 })
+        );
+    }
+
+    #[tokio::test]
+    async fn redirect_should_be_checked() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let res = "HTTP/1.1 302 Found\r\nLocation: http://localhost:1/a.csv\r\nContent-Length: 0\r\n\r\n";
+            socket.write_all(res.as_bytes()).await.unwrap();
+        });
+
+        let options = QueryOptions {
+            allowed_hosts: Some(vec!["127.0.0.1".into()]),
+            ..Default::default()
+        };
+        let source = format!("http://{}/a.csv", addr);
+        let err = retrieve_data(&source, &options).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitError>(),
+            Some(&LimitError::NotAllowed("http://localhost:1/a.csv".into()))
+        );
+    }
+
+    #[test]
+    fn split_fragment_should_decode_values() {
+        let (name, params) = split_fragment("file:///a.xlsx#sheet=Q1%202022&range=A1:B2");
+        assert_eq!(name, "file:///a.xlsx");
+        assert_eq!(params["sheet"], "Q1 2022");
+        assert_eq!(params["range"], "A1:B2");
     }
 }
@@ -0,0 +1,284 @@
+use crate::time::{
+    date_series, datetime_series, parse_interval, time_bucket, to_datetimes, TimeBucket,
+};
+use crate::{udf, DataSet};
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use sqlparser::ast::{Expr as SqlExpr, Function, FunctionArg, SelectItem, Value as SqlValue};
+use std::collections::BTreeSet;
+
+/// 补齐空缺时最多生成多少行，避免间隔太小时撑爆内存
+const MAX_GAP_ROWS: usize = 1_000_000;
+
+/// 内置的函数，注册同名的自定义函数不会覆盖它们
+const BUILTINS: [&str; 4] = [
+    "strptime",
+    "date_trunc",
+    "time_bucket",
+    "time_bucket_gapfill",
+];
+
+/// 格式里有这些就是日期时间，否则是日期
+const TIME_SPECIFIERS: [&str; 6] = ["%H", "%M", "%S", "%T", "%R", "%I"];
+
+/// `time_bucket_gapfill` 需要补齐的列：按 step 把缺少的时间点补上，其它列为空
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GapFill {
+    pub(crate) column: String,
+    pub(crate) step: Duration,
+}
+
+/// 把 SQL 函数转换成 DataFrame 的 Expr。支持的函数：
+/// - `strptime(col, '%d/%m/%Y')`：按格式把字符串解析成日期或者日期时间
+/// - `date_trunc('week', col)`：截断到所在的天 / 周 / 月 / 季度 / 年
+/// - `time_bucket('7 days', col)`：按固定间隔分桶
+/// - `time_bucket_gapfill('1 day', col)`：和 time_bucket 一样，并且在结果中补齐缺少的时间点
+pub(crate) fn to_expr(f: &Function) -> Result<Expr> {
+    let name = f.name.to_string().to_lowercase();
+    match (name.as_str(), f.args.as_slice()) {
+        ("strptime", [FunctionArg::Unnamed(expr), FunctionArg::Unnamed(fmt)]) => {
+            let fmt = string_arg(fmt)?;
+            strptime(expr_arg(expr)?, fmt)
+        }
+        ("date_trunc", [FunctionArg::Unnamed(unit), FunctionArg::Unnamed(expr)]) => {
+            let bucket: TimeBucket = string_arg(unit)?.parse()?;
+            Ok(date_trunc(expr_arg(expr)?, bucket))
+        }
+        (
+            "time_bucket" | "time_bucket_gapfill",
+            [FunctionArg::Unnamed(interval), FunctionArg::Unnamed(expr)],
+        ) => {
+            let interval = parse_interval(&string_arg(interval)?)?;
+            Ok(bucket(expr_arg(expr)?, interval))
+        }
+        ("strptime" | "date_trunc" | "time_bucket" | "time_bucket_gapfill", _) => {
+            Err(anyhow!("Invalid arguments for function {}", f))
+        }
+        // 自定义函数在过滤之前就计算好了，结果是以调用的 SQL 命名的一列
+        _ if udf::is_registered(&name) => Ok(col(&f.to_string())),
+        _ => Err(anyhow!("Function {} is not supported", f.name)),
+    }
+}
+
+/// 是否是内置的函数
+pub(crate) fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name.to_lowercase().as_str())
+}
+
+/// 如果投影是 time_bucket_gapfill(...)，返回需要补齐的列
+pub(crate) fn gap_fill(item: &SelectItem) -> Result<Option<GapFill>> {
+    let (expr, column) = match item {
+        SelectItem::UnnamedExpr(expr) => (expr, expr.to_string()),
+        SelectItem::ExprWithAlias { expr, alias } => (expr, alias.value.clone()),
+        _ => return Ok(None),
+    };
+    match expr {
+        SqlExpr::Function(f)
+            if f.name
+                .to_string()
+                .eq_ignore_ascii_case("time_bucket_gapfill") =>
+        {
+            match f.args.first() {
+                Some(FunctionArg::Unnamed(interval)) => {
+                    let step = parse_interval(&string_arg(interval)?)?;
+                    Ok(Some(GapFill { column, step }))
+                }
+                _ => Err(anyhow!("Invalid arguments for function {}", f)),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+impl DataSet {
+    /// 按 step 补齐 column 中缺少的时间点，补上的行其它列为空，结果按 column 排序
+    pub(crate) fn fill_gaps(self, gap: &GapFill) -> Result<DataSet> {
+        let series = self.column(&gap.column)?;
+        let dtype = series.dtype().clone();
+        let existing: BTreeSet<NaiveDateTime> =
+            to_datetimes(series)?.into_iter().flatten().collect();
+        let (first, last) = match (existing.iter().next(), existing.iter().next_back()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Ok(self),
+        };
+
+        let mut missing = Vec::new();
+        let mut t = first;
+        while t <= last {
+            if !existing.contains(&t) {
+                missing.push(Some(t));
+                if missing.len() > MAX_GAP_ROWS {
+                    return Err(anyhow!(
+                        "Too many gaps to fill in {}, try a larger interval",
+                        gap.column
+                    ));
+                }
+            }
+            t += gap.step;
+        }
+        if missing.is_empty() {
+            return Ok(self);
+        }
+
+        let len = missing.len();
+        let columns = self
+            .get_columns()
+            .iter()
+            .map(|s| match s.name() == gap.column {
+                true => datetime_series(&gap.column, missing.clone())?.cast_with_dtype(&dtype),
+                false => Int32Chunked::full_null(s.name(), len)
+                    .into_series()
+                    .cast_with_dtype(s.dtype()),
+            })
+            .collect::<Result<Vec<_>, PolarsError>>()?;
+
+        let mut df = self.0;
+        df.vstack_mut(&DataFrame::new(columns)?)?;
+        Ok(DataSet(df.lazy().sort(&gap.column, false).collect()?))
+    }
+}
+
+fn strptime(expr: Expr, fmt: String) -> Result<Expr> {
+    let has_time = TIME_SPECIFIERS.iter().any(|v| fmt.contains(v));
+    let output = if has_time {
+        DataType::Date64
+    } else {
+        DataType::Date32
+    };
+    let f = move |s: Series| {
+        let values = s.cast_with_dtype(&DataType::Utf8)?;
+        let values = values.utf8()?.into_iter();
+        if has_time {
+            let parsed = values
+                .map(|v| v.and_then(|v| NaiveDateTime::parse_from_str(v.trim(), &fmt).ok()))
+                .collect();
+            datetime_series(s.name(), parsed)
+        } else {
+            let parsed = values
+                .map(|v| v.and_then(|v| NaiveDate::parse_from_str(v.trim(), &fmt).ok()))
+                .collect();
+            date_series(s.name(), parsed)
+        }
+    };
+    Ok(expr.map(f, Some(output)))
+}
+
+fn date_trunc(expr: Expr, bucket: TimeBucket) -> Expr {
+    let f = move |s: Series| {
+        let values = to_datetimes(&s)?
+            .into_iter()
+            .map(|v| v.map(|dt| bucket.truncate(dt).date()))
+            .collect();
+        date_series(s.name(), values)
+    };
+    expr.map(f, Some(DataType::Date32))
+}
+
+/// 间隔是整数天的时候结果是日期，否则是日期时间
+fn bucket(expr: Expr, interval: Duration) -> Expr {
+    let whole_days = interval.num_seconds() % Duration::days(1).num_seconds() == 0;
+    let output = if whole_days {
+        DataType::Date32
+    } else {
+        DataType::Date64
+    };
+    let f = move |s: Series| {
+        let values = to_datetimes(&s)?
+            .into_iter()
+            .map(|v| v.map(|dt| time_bucket(interval, dt)));
+        match whole_days {
+            true => date_series(s.name(), values.map(|v| v.map(|dt| dt.date())).collect()),
+            false => datetime_series(s.name(), values.collect()),
+        }
+    };
+    expr.map(f, Some(output))
+}
+
+fn expr_arg(expr: &SqlExpr) -> Result<Expr> {
+    crate::convert::Expression(Box::new(expr.to_owned())).try_into()
+}
+
+fn string_arg(expr: &SqlExpr) -> Result<String> {
+    match expr {
+        SqlExpr::Value(SqlValue::SingleQuotedString(v)) => Ok(v.to_owned()),
+        v => Err(anyhow!("Expect a string literal, got {}", v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query;
+    use crate::testing::temp_source;
+
+    fn data() -> String {
+        temp_source(
+            "functions.csv",
+            "date,day,cases\n2021-08-02,02/08/2021,1\n2021-08-03,03/08/2021,2\n2021-08-10,10/08/2021,3\n2021-08-12,12/08/2021,4\n",
+        )
+    }
+
+    #[tokio::test]
+    async fn date_functions_should_work() {
+        let sql = format!(
+            "SELECT date_trunc('week', date) AS week, strptime(day, '%d/%m/%Y') AS day, cases \
+            FROM {} WHERE date >= '2021-08-03'",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 3);
+        let csv = ds.to_csv().unwrap();
+        assert!(csv.starts_with("week,day,cases\n2021-08-02,2021-08-03,2\n"));
+    }
+
+    #[tokio::test]
+    async fn date_literal_should_compare_on_both_sides() {
+        for condition in [
+            "date > '2021-08-03'",
+            "'2021-08-03' < date",
+            "date <= '2021-08-03'",
+            "'2021-08-03' >= date",
+        ] {
+            let sql = format!("SELECT cases FROM {} WHERE {}", data(), condition);
+            let ds = query(sql).await.unwrap();
+            assert_eq!(ds.height(), 2, "{}", condition);
+        }
+        let sql = format!("SELECT cases FROM {} WHERE 2 < cases", data());
+        assert_eq!(query(sql).await.unwrap().height(), 2);
+    }
+
+    #[tokio::test]
+    async fn time_bucket_gapfill_should_work() {
+        let sql = format!(
+            "SELECT time_bucket_gapfill('7 days', date) AS week, cases FROM {}",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        // 4 行数据在 08-02 和 08-09 两个桶里，中间没有空缺
+        assert_eq!(ds.height(), 4);
+
+        let sql = format!(
+            "SELECT time_bucket_gapfill('1 day', date) AS day, cases FROM {}",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        // 08-02 到 08-12 一共 11 天
+        assert_eq!(ds.height(), 11);
+        assert_eq!(ds.column("cases").unwrap().null_count(), 7);
+    }
+
+    #[tokio::test]
+    async fn gapfill_should_happen_before_limit() {
+        let sql = format!(
+            "SELECT time_bucket_gapfill('1 day', date) AS day, cases FROM {} \
+            ORDER BY day DESC LIMIT 3",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        // 08-12、08-11（补齐的）、08-10
+        assert_eq!(ds.height(), 3);
+        assert_eq!(ds.column("cases").unwrap().null_count(), 1);
+        let csv = ds.to_csv().unwrap();
+        assert!(csv.starts_with("day,cases\n2021-08-12,4\n2021-08-11,\n"));
+    }
+}
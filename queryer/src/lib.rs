@@ -1,19 +1,56 @@
 use crate::convert::Sql;
-use crate::fetcher::retrieve_data;
-use crate::loader::detect_content;
-use anyhow::{anyhow, Result};
+use crate::planner::Plan;
+use anyhow::Result;
 use polars::prelude::*;
-use sqlparser::parser::Parser;
+use sqlparser::ast::Statement;
 use std::ops::{Deref, DerefMut};
-use tracing::info;
 
+mod chart;
+mod command;
+mod compression;
 mod convert;
+mod database;
 mod dialect;
+mod editor;
+mod error;
+mod excel;
 mod fetcher;
+mod functions;
 mod loader;
+mod materialize;
+mod memory;
+mod options;
+mod output;
+mod params;
+mod planner;
+mod pushdown;
+mod reshape;
+mod sample;
+mod schema;
+mod script;
+mod session;
+mod summary;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+mod time;
+mod udf;
 
+pub use chart::{Aggregation, Axis, Chart, ChartSeries, ChartSpec};
 pub use dialect::example_sql;
 pub use dialect::TyrDialect;
+pub use editor::{
+    complete, validate, Catalog, Completion, CompletionKind, Diagnostic, DiagnosticKind, Span,
+};
+pub use error::ErrorKind;
+pub use loader::Cell;
+pub use memory::{deregister_memory_table, register_memory_table};
+pub use options::{LimitError, QueryOptions};
+pub use output::OutputFormat;
+pub use params::{bind_params, Param};
+pub use schema::{schema, ColumnInfo};
+pub use session::Session;
+pub use time::TimeBucket;
+pub use udf::{deregister_function, register_function, register_row_function, ScalarFunction};
 
 #[derive(Debug)]
 pub struct DataSet(DataFrame);
@@ -34,8 +71,28 @@ impl DerefMut for DataSet {
     }
 }
 
+impl From<DataFrame> for DataSet {
+    fn from(df: DataFrame) -> Self {
+        DataSet(df)
+    }
+}
+
+impl From<DataSet> for DataFrame {
+    fn from(ds: DataSet) -> Self {
+        ds.0
+    }
+}
+
 /// 实现 DataSet 自己的方法
 impl DataSet {
+    /// 用 SQL 查询这个 DataSet，SQL 中用 `self` 表示它，比如 `SELECT a FROM self WHERE b > 1`。
+    /// 结果还是 DataSet，可以继续链式查询
+    pub async fn sql<T: AsRef<str>>(&self, sql: T) -> Result<DataSet> {
+        let mut session = Session::new();
+        session.register_dataframe("self", self.0.clone());
+        session.query(sql).await
+    }
+
     /// 从 DataSet 转换成 csv
     pub fn to_csv(&self) -> Result<String> {
         let mut buf = Vec::new();
@@ -43,41 +100,119 @@ impl DataSet {
         writer.finish(self)?;
         Ok(String::from_utf8(buf)?)
     }
+
+    /// 取出从 offset 开始的 len 行，方便分页展示
+    pub fn page(&self, offset: usize, len: usize) -> DataSet {
+        DataSet(self.slice(offset as i64, len))
+    }
+
+    /// 从 DataSet 转换成 json，格式是对象数组：[{"列名": 值, ...}, ...]
+    pub fn to_json(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        JsonWriter::new(&mut buf).finish(self)?;
+        // polars 的 JsonWriter 每行输出一个对象，拼接成数组
+        let rows: Vec<&str> = std::str::from_utf8(&buf)?.lines().collect();
+        Ok(format!("[{}]", rows.join(",")))
+    }
 }
 
-/// 从 from 中获取数据，从 where 中过滤，最后选取需要返回的列
+/// 执行一条 SQL 语句：SELECT 查询，或者 DESCRIBE / SUMMARIZE 之类的扩展语句
 pub async fn query<T: AsRef<str>>(sql: T) -> Result<DataSet> {
-    let ast = Parser::parse_sql(&TyrDialect::default(), sql.as_ref())?;
-    if ast.len() != 1 {
-        return Err(anyhow!("Only support single sql at the moment"));
-    }
-    let sql = &ast[0];
+    Session::default().query(sql).await
+}
+
+/// 和 query 一样，但是受 options 中的资源限制
+pub async fn query_with_options<T: AsRef<str>>(sql: T, options: QueryOptions) -> Result<DataSet> {
+    Session::default().with_options(options).query(sql).await
+}
+
+/// 从 from 中获取数据，从 where 中过滤，最后选取需要返回的列
+async fn select(statement: &Statement, session: &Session) -> Result<DataSet> {
     // 整个 SQL AST 转换成我们定义的 Sql 结构的细节都埋藏在 try_into() 中
     // 我们只需关注数据结构的使用，怎么转换可以之后需要的时候才关注，这是
     // 关注点分离，是我们控制软件复杂度的法宝。
+    let sql: Sql = statement.try_into()?;
+    // 从 source 读入一个 DataSet，source 可以是数据源地址，也可以是 session 中注册的表名。
+    // planner 决定每个数据源只读取哪些列，数据库类型的数据源会尽量在源头执行
+    // WHERE / ORDER BY / LIMIT；有 JOIN 时，每个数据源读入之后再连接起来
+    let df = Plan::new(&sql).execute(session).await?;
+    // polars 的计算是同步的，放到专门的线程池里执行，这样超时对计算也能生效
+    let statement = statement.clone();
+    tokio::task::spawn_blocking(move || compute(&statement, df)).await?
+}
+
+/// 对读入的数据执行 WHERE / ORDER BY / LIMIT 和投影
+fn compute(statement: &Statement, df: DataFrame) -> Result<DataSet> {
+    let sql: Sql = statement.try_into()?;
+    let plan = Plan::new(&sql);
     let Sql {
         selection,
         condition,
-        source,
         order_by,
         offset,
         limit,
-    } = sql.try_into()?;
-    info!("retrieving data from source: {}", source);
-    // 从 source 读入一个 DataSet
-    // detect_content，怎么 detect 不用要，重要的是它能根据内容返回 DataSet
-    let ds = detect_content(retrieve_data(source).await?).load()?;
-    let mut filtered = match condition {
-        Some(expr) => ds.0.lazy().filter(expr),
-        None => ds.0.lazy(),
+        gap_fill,
+        udfs,
+        ..
+    } = sql;
+    let selection = plan.expand(selection, &df);
+    // 自定义函数在过滤之前对所有的行计算好，作为额外的列参与之后的计算
+    let (df, selection) = udf::apply(&udfs, df, selection)?;
+    let filtered = match condition {
+        Some(expr) => df.lazy().filter(expr),
+        None => df.lazy(),
+    };
+    // time_bucket_gapfill 补齐的时间点只在投影之后才有，要先补齐再排序和截断，
+    // 这时 ORDER BY 只能用投影中的列
+    let (mut df, selection) = match gap_fill {
+        Some(gap) => {
+            let ds = DataSet(filtered.select(selection).collect()?);
+            (ds.fill_gaps(&gap)?.0, None)
+        }
+        None => (filtered.collect()?, Some(selection)),
     };
-    filtered = order_by
-        .into_iter()
-        .fold(filtered, |acc, (col, desc)| acc.sort(&col, desc));
+    // 多列排序要一次完成，依次排序的话最后一列会变成主排序键。
+    // 默认空值升序时在前、降序时在后；指定了 NULLS FIRST / LAST 时，
+    // 先按一个临时的“是否为空”列排序，排完再删掉
+    if !order_by.is_empty() {
+        let mut lazy = df.lazy();
+        let mut nulls = Vec::new();
+        let mut columns = Vec::new();
+        let mut reverse = Vec::new();
+        for (i, (name, desc, nulls_first)) in order_by.into_iter().enumerate() {
+            if let Some(first) = nulls_first {
+                let key = format!("__nulls_{}", i);
+                lazy = lazy.with_column(col(&name).is_null().alias(&key));
+                columns.push(col(&key));
+                reverse.push(first);
+                nulls.push(key);
+            }
+            columns.push(col(&name));
+            reverse.push(desc);
+        }
+        df = lazy.sort_by_exprs(columns, reverse).collect()?;
+        for key in nulls {
+            df.drop_in_place(&key)?;
+        }
+    }
     if offset.is_some() || limit.is_some() {
-        filtered = filtered.slice(offset.unwrap_or(0), limit.unwrap_or(usize::MAX));
+        // polars 的 slice 在 offset 不小于行数时得到的列没有数据块，之后的计算会 panic
+        let offset = offset.unwrap_or(0).max(0);
+        df = match offset < df.height() as i64 {
+            true => df.slice(offset, limit.unwrap_or(usize::MAX)),
+            false => df.slice(0, 0),
+        };
     }
-    Ok(DataSet(filtered.select(selection).collect()?))
+    if let Some(selection) = selection {
+        df = df.lazy().select(selection).collect()?;
+    }
+    Ok(DataSet(plan.unqualify(df)?))
+}
+
+/// 不执行查询，只返回查询计划
+fn explain(sql: &Statement, session: &Session) -> Result<DataSet> {
+    let sql: Sql = sql.try_into()?;
+    Plan::new(&sql).explain(session)
 }
 
 #[cfg(test)]
@@ -1,3 +1,6 @@
+use crate::excel::{ExcelLoader, SheetFormat};
+use crate::fetcher::split_fragment;
+use crate::time::{parse_dates, to_datetimes};
 use crate::DataSet;
 use anyhow::Result;
 use polars::prelude::*;
@@ -12,31 +15,195 @@ pub trait Load {
 #[non_exhaustive]
 pub enum Loader {
     Csv(CsvLoader),
+    Excel(ExcelLoader),
 }
 
 #[derive(Default, Debug)]
 pub struct CsvLoader(pub(crate) String);
 
+/// 一个标量值：数据库查询返回的值，或者逐行计算的自定义函数的参数和结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// 数据库查询返回的结果：列名和按行存放的值
+#[derive(Default, Debug)]
+pub struct Rows {
+    pub(crate) columns: Vec<String>,
+    pub(crate) rows: Vec<Vec<Cell>>,
+}
+
+/// 把数据库返回的行转换成 DataFrame，每一列的类型根据这一列的值推断。
+/// 和 csv 一样，都是日期的字符串列转换成 Date32 / Date64
+#[derive(Default, Debug)]
+pub struct SqlLoader(pub(crate) Rows);
+
 impl Loader {
     pub fn load(self) -> Result<DataSet> {
         match self {
             Loader::Csv(csv) => csv.load(),
+            Loader::Excel(excel) => excel.load(),
+        }
+    }
+
+    /// 只读取 columns 中的列。csv 在解析时就跳过其它列，其它格式读入之后再选取
+    pub fn load_columns(self, columns: Option<&[String]>) -> Result<DataSet> {
+        match (self, columns) {
+            (Loader::Csv(csv), Some(columns)) => csv.read(Some(columns.to_vec())),
+            (loader, Some(columns)) => {
+                let columns: Vec<&str> = columns.iter().map(|v| v.as_str()).collect();
+                Ok(DataSet(loader.load()?.select(columns)?))
+            }
+            (loader, None) => loader.load(),
         }
     }
 }
 
-pub fn detect_content(data: String) -> Loader {
-    // TODO: 内容检测
-    Loader::Csv(CsvLoader(data))
+/// 根据内容选择 Loader：电子表格通过 magic bytes 识别，其它的当作 csv。
+/// 电子表格可以在数据源的 `#` 之后指定 sheet 和 range
+pub fn detect_content(source: &str, data: Vec<u8>) -> Result<Loader> {
+    if let Some(format) = SheetFormat::detect(&data) {
+        let (_, params) = split_fragment(source);
+        return Ok(Loader::Excel(ExcelLoader {
+            data,
+            format,
+            sheet: params.get("sheet").map(|v| v.to_string()),
+            range: params.get("range").map(|v| v.to_string()),
+        }));
+    }
+    Ok(Loader::Csv(CsvLoader(String::from_utf8(data)?)))
 }
 
 impl Load for CsvLoader {
     type Error = anyhow::Error;
 
     fn load(self) -> Result<DataSet, Self::Error> {
+        self.read(None)
+    }
+}
+
+impl CsvLoader {
+    fn read(self, columns: Option<Vec<String>>) -> Result<DataSet> {
         let df = CsvReader::new(Cursor::new(self.0))
             .infer_schema(Some(16))
+            .with_columns(columns)
             .finish()?;
-        Ok(DataSet(df))
+        // 都是日期或者日期时间的字符串列，转换成 Date32 / Date64
+        Ok(DataSet(parse_dates(df)?))
+    }
+}
+
+impl Load for SqlLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let Rows { columns, rows } = self.0;
+        let series = columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let cells: Vec<&Cell> = rows.iter().map(|row| &row[i]).collect();
+                to_series(name, &cells)
+            })
+            .collect();
+        Ok(DataSet(parse_dates(DataFrame::new(series)?)?))
     }
 }
+
+/// 一列里都是整数就是 i64，整数和浮点数混合是 f64，都是布尔值就是 bool，其它情况当作字符串
+pub(crate) fn to_series(name: &str, cells: &[&Cell]) -> Series {
+    let values = cells.iter().filter(|v| !matches!(v, Cell::Null));
+    let all = |f: fn(&Cell) -> bool| values.clone().all(|v| f(v));
+
+    if all(|v| matches!(v, Cell::Int(_))) {
+        let data: Vec<Option<i64>> = cells
+            .iter()
+            .map(|v| match v {
+                Cell::Int(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, data)
+    } else if all(|v| matches!(v, Cell::Int(_) | Cell::Float(_))) {
+        let data: Vec<Option<f64>> = cells
+            .iter()
+            .map(|v| match v {
+                Cell::Int(v) => Some(*v as f64),
+                Cell::Float(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, data)
+    } else if all(|v| matches!(v, Cell::Bool(_))) {
+        let data: Vec<Option<bool>> = cells
+            .iter()
+            .map(|v| match v {
+                Cell::Bool(v) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        Series::new(name, data)
+    } else {
+        let data: Vec<Option<String>> = cells
+            .iter()
+            .map(|v| match v {
+                Cell::Null => None,
+                Cell::Bool(v) => Some(v.to_string()),
+                Cell::Int(v) => Some(v.to_string()),
+                Cell::Float(v) => Some(v.to_string()),
+                Cell::Text(v) => Some(v.to_owned()),
+            })
+            .collect();
+        Series::new(name, data)
+    }
+}
+
+/// 把一列转换成逐行的值，日期和其它类型都转换成字符串
+pub(crate) fn to_cells(s: &Series) -> Result<Vec<Cell>> {
+    let cells = match s.dtype() {
+        DataType::Boolean => s
+            .bool()?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, Cell::Bool))
+            .collect(),
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => s
+            .cast_with_dtype(&DataType::Int64)?
+            .i64()?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, Cell::Int))
+            .collect(),
+        DataType::Float32 | DataType::Float64 => s
+            .cast_with_dtype(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, Cell::Float))
+            .collect(),
+        DataType::Date32 => to_datetimes(s)?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, |v| Cell::Text(v.date().to_string())))
+            .collect(),
+        DataType::Date64 => to_datetimes(s)?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, |v| Cell::Text(v.to_string())))
+            .collect(),
+        _ => s
+            .cast_with_dtype(&DataType::Utf8)?
+            .utf8()?
+            .into_iter()
+            .map(|v| v.map_or(Cell::Null, |v| Cell::Text(v.to_owned())))
+            .collect(),
+    };
+    Ok(cells)
+}
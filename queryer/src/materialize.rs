@@ -0,0 +1,349 @@
+use crate::database::{self, quote_identifier};
+use crate::fetcher::{file_path, retrieve_data};
+use crate::loader::{detect_content, to_cells, Cell, CsvLoader, Load, Loader};
+use crate::pushdown::Pushdown;
+use crate::summary::is_numeric;
+use crate::QueryOptions;
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use std::cmp::Ordering;
+use std::io::SeekFrom;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::info;
+
+/// 物化的表：数据读入之后和注册的 DataFrame 一样保存在会话中，这里只记录刷新需要的状态，
+/// 刷新时只读取新增的行追加进来。watermark 是只增不减的列，比如时间或者自增的 id
+#[derive(Debug, Clone)]
+pub(crate) struct Materialized {
+    pub(crate) source: String,
+    watermark: String,
+    /// 追加写入的 csv 文件已经读到的位置；其它数据源刷新时用 watermark 过滤
+    offset: Option<Offset>,
+}
+
+#[derive(Debug, Clone)]
+struct Offset {
+    path: String,
+    header: String,
+    position: u64,
+}
+
+impl Materialized {
+    pub(crate) async fn load(
+        source: String,
+        watermark: String,
+        options: &QueryOptions,
+    ) -> Result<(Self, DataFrame)> {
+        let mut table = Self {
+            source,
+            watermark,
+            offset: None,
+        };
+        let data = table.reload(options).await?;
+        Ok((table, data))
+    }
+
+    /// 读取新增的行追加到 data 中，返回新增的行数
+    pub(crate) async fn refresh(
+        &mut self,
+        data: &mut DataFrame,
+        options: &QueryOptions,
+    ) -> Result<usize> {
+        if let Some(offset) = self.offset.clone() {
+            let len = tokio::fs::metadata(&offset.path).await?.len();
+            // 文件变短了说明被截断或者替换了，只能重新读取
+            if len < offset.position {
+                info!("{} is truncated, reloading", self.source);
+                let before = data.height();
+                *data = self.reload(options).await?;
+                return Ok(data.height().saturating_sub(before));
+            }
+            return self.read_appended(data, offset, options).await;
+        }
+
+        let high = self.high_watermark(data)?;
+        let df = match (&high, database::is_database(&self.source)) {
+            (Some(high), true) => {
+                options.check_source(&self.source)?;
+                let filter = format!("{} > {}", quote_identifier(&self.watermark), literal(high)?);
+                let pushdown = Pushdown::new(Some(filter), &[], None, None);
+                database::load(&self.source, &pushdown).await?.0
+            }
+            // 不支持增量读取的数据源，读入之后只保留 watermark 更大的行
+            _ => self.fetch(options).await?.0,
+        };
+        let df = match high {
+            Some(high) => {
+                let mask: BooleanChunked = to_cells(df.column(&self.watermark)?)?
+                    .iter()
+                    .map(|v| compare(v, &high) == Some(Ordering::Greater))
+                    .collect();
+                df.filter(&mask)?
+            }
+            None => df,
+        };
+        append(data, df)
+    }
+
+    async fn reload(&mut self, options: &QueryOptions) -> Result<DataFrame> {
+        let (df, offset) = self.fetch(options).await?;
+        df.column(&self.watermark)
+            .map_err(|_| anyhow!("Watermark column {} not found", self.watermark))?;
+        self.offset = offset;
+        Ok(df)
+    }
+
+    /// 读入全部数据。没有压缩的 csv 文件只读取完整的行，记下读到的位置
+    async fn fetch(&self, options: &QueryOptions) -> Result<(DataFrame, Option<Offset>)> {
+        if database::is_database(&self.source) {
+            options.check_source(&self.source)?;
+            let df = database::load(&self.source, &Pushdown::default()).await?.0;
+            return Ok((df, None));
+        }
+
+        let data = retrieve_data(&self.source, options).await?;
+        let path = match file_path(&self.source) {
+            Some(path) if !self.source.contains('#') => path,
+            _ => return Ok((detect_content(&self.source, data)?.load()?.0, None)),
+        };
+        // 解压之后长度会变，这时不能按位置增量读取
+        let len = tokio::fs::metadata(&path).await?.len();
+        let loader = detect_content(&self.source, data)?;
+        let data = match loader {
+            Loader::Csv(csv) if csv.0.len() as u64 == len => csv.0,
+            loader => return Ok((loader.load()?.0, None)),
+        };
+        let header = data.lines().next().unwrap_or_default().to_owned();
+        // 最后一行没有换行符时可能还没有写完，下次刷新时再读
+        let end = data.rfind('\n').map_or(0, |i| i + 1);
+        let df = CsvLoader(data[..end].to_owned()).load()?.0;
+        let offset = Offset {
+            path,
+            header,
+            position: end as u64,
+        };
+        Ok((df, Some(offset)))
+    }
+
+    /// 从上次读到的位置开始读取追加的行
+    async fn read_appended(
+        &mut self,
+        data: &mut DataFrame,
+        mut offset: Offset,
+        options: &QueryOptions,
+    ) -> Result<usize> {
+        let mut file = File::open(&offset.path).await?;
+        file.seek(SeekFrom::Start(offset.position)).await?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended).await?;
+        options.check_bytes(appended.len() as u64)?;
+
+        let end = appended
+            .iter()
+            .rposition(|v| *v == b'\n')
+            .map_or(0, |i| i + 1);
+        if end == 0 {
+            return Ok(0);
+        }
+        let csv = format!(
+            "{}\n{}",
+            offset.header,
+            std::str::from_utf8(&appended[..end])?
+        );
+        let rows = append(data, CsvLoader(csv).load()?.0)?;
+        offset.position += end as u64;
+        self.offset = Some(offset);
+        Ok(rows)
+    }
+
+    /// 当前 watermark 列的最大值，表是空的时候是 None
+    fn high_watermark(&self, data: &DataFrame) -> Result<Option<Cell>> {
+        let cells = to_cells(data.column(&self.watermark)?)?;
+        Ok(cells
+            .into_iter()
+            .filter(|v| *v != Cell::Null)
+            .fold(None, |max, v| match max {
+                Some(max) if compare(&v, &max) != Some(Ordering::Greater) => Some(max),
+                _ => Some(v),
+            }))
+    }
+}
+
+/// 新的行按照已有的列追加到表中。类型不一样时数字放宽成两边都能表示的类型，
+/// 已有的列也一起转换，其他情况直接报错，避免 1.5 追加到整数列里变成 1
+fn append(data: &mut DataFrame, df: DataFrame) -> Result<usize> {
+    let mut existing = Vec::new();
+    let mut appended = Vec::new();
+    for s in data.get_columns() {
+        let new = df.column(s.name())?;
+        let dtype = widen(s, new)?;
+        existing.push(s.cast_with_dtype(&dtype)?);
+        appended.push(new.cast_with_dtype(&dtype)?);
+    }
+    let df = DataFrame::new(appended)?;
+    *data = DataFrame::new(existing)?;
+    data.vstack_mut(&df)?;
+    Ok(df.height())
+}
+
+/// 已有的列和新读入的列都能无损转换过去的类型
+fn widen(existing: &Series, new: &Series) -> Result<DataType> {
+    let (a, b) = (existing.dtype(), new.dtype());
+    if a == b || new.null_count() == new.len() {
+        return Ok(a.clone());
+    }
+    if is_numeric(a) && is_numeric(b) {
+        let float = |v: &DataType| matches!(v, DataType::Float32 | DataType::Float64);
+        return Ok(match float(a) || float(b) {
+            true => DataType::Float64,
+            false => DataType::Int64,
+        });
+    }
+    Err(anyhow!(
+        "Can't append {:?} values to column {} of type {:?}",
+        b,
+        existing.name(),
+        a
+    ))
+}
+
+/// 数字之间、字符串之间、布尔值之间可以比较，日期已经转换成可以按字符串比较的格式
+fn compare(a: &Cell, b: &Cell) -> Option<Ordering> {
+    match (a, b) {
+        (Cell::Int(a), Cell::Int(b)) => Some(a.cmp(b)),
+        (Cell::Int(a), Cell::Float(b)) => (*a as f64).partial_cmp(b),
+        (Cell::Float(a), Cell::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Cell::Float(a), Cell::Float(b)) => a.partial_cmp(b),
+        (Cell::Text(a), Cell::Text(b)) => Some(a.cmp(b)),
+        (Cell::Bool(a), Cell::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// 把值转换成 SQL 字面量，拼到发给数据库的过滤条件里。NaN 和无穷大没有对应的字面量
+fn literal(value: &Cell) -> Result<String> {
+    let v = match value {
+        Cell::Null => "NULL".to_owned(),
+        Cell::Bool(v) => v.to_string(),
+        Cell::Int(v) => v.to_string(),
+        Cell::Float(v) if !v.is_finite() => {
+            return Err(anyhow!("Watermark {} can't be used in a SQL filter", v))
+        }
+        Cell::Float(v) => v.to_string(),
+        Cell::Text(v) => format!("'{}'", v.replace('\'', "''")),
+    };
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+    use crate::Session;
+    use std::io::Write;
+
+    #[test]
+    fn literal_should_work() {
+        assert_eq!(literal(&Cell::Float(1.5)).unwrap(), "1.5");
+        assert_eq!(literal(&Cell::Text("it's".into())).unwrap(), "'it''s'");
+        assert!(literal(&Cell::Float(f64::NAN)).is_err());
+        assert!(literal(&Cell::Float(f64::INFINITY)).is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_appended_csv_should_work() {
+        let path = temp_path("materialize.csv");
+        std::fs::write(&path, "id,name\n1,a\n2,b\n").unwrap();
+
+        let mut session = Session::new();
+        let source = format!("file://{}", path.display());
+        session.materialize("logs", &source, "id").await.unwrap();
+        assert_eq!(
+            session.query("SELECT * FROM logs").await.unwrap().height(),
+            2
+        );
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        // 最后一行还没有写完，先不读
+        file.write_all(b"3,c\n4,d").unwrap();
+        assert_eq!(session.refresh("logs").await.unwrap(), 1);
+        file.write_all(b"\n").unwrap();
+        assert_eq!(session.refresh("logs").await.unwrap(), 1);
+        assert_eq!(session.refresh("logs").await.unwrap(), 0);
+
+        let ds = session
+            .query("SELECT name FROM logs WHERE id > 2")
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 2);
+        assert!(session.refresh("missing").await.is_err());
+
+        // 物化的表和 DataFrame 在同一个地方，不能当作 DataFrame 取消注册
+        assert_eq!(session.catalog().tables, ["logs"]);
+        assert!(session.deregister_dataframe("logs").is_none());
+        assert_eq!(session.deregister("logs"), Some(source));
+        assert!(session.query("SELECT * FROM logs").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_should_widen_types() {
+        let path = temp_path("materialize_widen.csv");
+        std::fs::write(&path, "id,value\n1,1\n2,2\n").unwrap();
+
+        let mut session = Session::new();
+        let source = format!("file://{}", path.display());
+        session.materialize("values", &source, "id").await.unwrap();
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"3,1.5\n").unwrap();
+        assert_eq!(session.refresh("values").await.unwrap(), 1);
+        let ds = session.query("SELECT value FROM values").await.unwrap();
+        let values: Vec<_> = ds
+            .column("value")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(values, vec![Some(1.0), Some(2.0), Some(1.5)]);
+
+        file.write_all(b"4,abc\n").unwrap();
+        assert!(session.refresh("values").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refresh_database_should_work() {
+        let path = temp_path("materialize.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE events (id INTEGER, name TEXT);
+             INSERT INTO events VALUES (1, 'start'), (2, 'click');",
+        )
+        .unwrap();
+
+        let mut session = Session::new();
+        let source = format!("sqlite://{}?table=events", path.display());
+        session.materialize("events", &source, "id").await.unwrap();
+        conn.execute("INSERT INTO events VALUES (3, 'stop')", [])
+            .unwrap();
+        assert_eq!(session.refresh("events").await.unwrap(), 1);
+
+        let ds = session.query("SELECT name FROM events").await.unwrap();
+        assert_eq!(ds.height(), 3);
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(2),
+            Some("stop")
+        );
+        assert!(session
+            .materialize("bad", &source, "missing")
+            .await
+            .is_err());
+    }
+}
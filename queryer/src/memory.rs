@@ -0,0 +1,100 @@
+use crate::DataSet;
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use polars::prelude::*;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// 内存中的表：名字到 DataFrame。全局的 `mem://` 表和会话中注册的 DataFrame、物化的表都保存在这里
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MemoryTables(HashMap<String, DataFrame>);
+
+/// 全局的内存表，所有查询共享，用 `mem://<name>` 引用
+static TABLES: Lazy<RwLock<MemoryTables>> = Lazy::new(Default::default);
+
+impl MemoryTables {
+    pub(crate) fn insert(&mut self, name: String, df: DataFrame) {
+        self.0.insert(name, df);
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Option<DataFrame> {
+        self.0.remove(name)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&DataFrame> {
+        self.0.get(name)
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub(crate) fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|k| k.as_str())
+    }
+
+    /// 读取表，只取 columns 中的列。DataFrame 的列是共享的，不会复制数据
+    pub(crate) fn load(&self, name: &str, columns: Option<&[String]>) -> Result<Option<DataSet>> {
+        let df = match self.0.get(name) {
+            Some(df) => df,
+            None => return Ok(None),
+        };
+        match columns {
+            Some(columns) => {
+                let columns: Vec<&str> = columns.iter().map(|v| v.as_str()).collect();
+                Ok(Some(DataSet(df.select(columns)?)))
+            }
+            None => Ok(Some(DataSet(df.clone()))),
+        }
+    }
+}
+
+/// 把 DataFrame 注册成内存中的表，之后可以 `SELECT * FROM mem://<name>`。同名的表会被替换
+pub fn register_memory_table(name: impl Into<String>, df: DataFrame) {
+    TABLES.write().unwrap().insert(name.into(), df);
+}
+
+/// 取消注册，返回原来的表
+pub fn deregister_memory_table(name: &str) -> Option<DataFrame> {
+    TABLES.write().unwrap().remove(name)
+}
+
+pub(crate) fn is_memory(source: &str) -> bool {
+    source.starts_with("mem://")
+}
+
+/// 读取 `mem://<name>` 对应的全局内存表，只取 columns 中的列
+pub(crate) fn load(source: &str, columns: Option<&[String]>) -> Result<DataSet> {
+    let name = &source["mem://".len()..];
+    TABLES
+        .read()
+        .unwrap()
+        .load(name, columns)?
+        .ok_or_else(|| anyhow!("Memory table {} not found", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+
+    #[tokio::test]
+    async fn memory_table_should_work() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["tom", "jerry"]),
+            Series::new("age", &[10i64, 3]),
+        ])
+        .unwrap();
+        register_memory_table("memory_pets", df);
+        let ds = query("SELECT name FROM mem://memory_pets WHERE age > 5")
+            .await
+            .unwrap();
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("tom")
+        );
+
+        assert!(deregister_memory_table("memory_pets").is_some());
+        assert!(query("SELECT name FROM mem://memory_pets").await.is_err());
+    }
+}
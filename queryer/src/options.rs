@@ -0,0 +1,155 @@
+use anyhow::Result;
+use polars::prelude::{DataFrame, DataType};
+use std::fmt;
+use std::time::Duration;
+use url::Url;
+
+/// 查询的资源限制，默认没有任何限制。在共享的服务里使用时，
+/// 可以避免一个查询下载过大的数据源，或者一直运行下去
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// 数据源（解压后）最多多少字节。数据库和 `mem://` 数据源没有原始字节，按读入后每列的大小估算
+    pub max_source_bytes: Option<u64>,
+    /// 查询结果最多多少行
+    pub max_rows: Option<usize>,
+    /// 整个查询的超时时间，包括获取数据和计算。超时后立即返回错误，已经开始的计算会在后台跑完
+    pub timeout: Option<Duration>,
+    /// 允许的数据源 scheme，比如 ["https", "file"]
+    pub allowed_schemes: Option<Vec<String>>,
+    /// 允许的主机名，`example.com` 也允许它的子域名
+    pub allowed_hosts: Option<Vec<String>>,
+}
+
+/// 超过 QueryOptions 中的限制时返回的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    /// 数据源超过了 max_source_bytes
+    SourceTooLarge { limit: u64 },
+    /// 结果超过了 max_rows
+    TooManyRows { rows: usize, limit: usize },
+    /// 超过了 timeout
+    Timeout(Duration),
+    /// 数据源不在 allowed_schemes / allowed_hosts 中
+    NotAllowed(String),
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::SourceTooLarge { limit } => {
+                write!(f, "Data source exceeds the limit of {} bytes", limit)
+            }
+            LimitError::TooManyRows { rows, limit } => write!(
+                f,
+                "Query returns {} rows, exceeds the limit of {} rows",
+                rows, limit
+            ),
+            LimitError::Timeout(t) => write!(f, "Query timed out after {:?}", t),
+            LimitError::NotAllowed(source) => write!(f, "Data source {} is not allowed", source),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl QueryOptions {
+    /// 检查数据源的 scheme 和主机名是否允许访问
+    pub fn check_source(&self, source: &str) -> Result<(), LimitError> {
+        if self.allowed_schemes.is_none() && self.allowed_hosts.is_none() {
+            return Ok(());
+        }
+        let url = Url::parse(source).map_err(|_| LimitError::NotAllowed(source.to_owned()))?;
+        if let Some(schemes) = &self.allowed_schemes {
+            if !schemes.iter().any(|v| v.eq_ignore_ascii_case(url.scheme())) {
+                return Err(LimitError::NotAllowed(source.to_owned()));
+            }
+        }
+        if let Some(hosts) = &self.allowed_hosts {
+            // 本地文件没有主机名，只受 allowed_schemes 限制
+            if let Some(host) = url.host_str() {
+                let host = host.to_lowercase();
+                let allowed = hosts.iter().any(|v| {
+                    let v = v.to_lowercase();
+                    host == v || host.ends_with(&format!(".{}", v))
+                });
+                if !allowed {
+                    return Err(LimitError::NotAllowed(source.to_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 检查数据大小是否超过 max_source_bytes
+    pub(crate) fn check_bytes(&self, len: u64) -> Result<(), LimitError> {
+        match self.max_source_bytes {
+            Some(limit) if len > limit => Err(LimitError::SourceTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// 检查读入的 DataFrame 估算出来的大小是否超过 max_source_bytes
+    pub(crate) fn check_frame(&self, df: &DataFrame) -> Result<(), LimitError> {
+        if self.max_source_bytes.is_none() {
+            return Ok(());
+        }
+        self.check_bytes(estimated_bytes(df))
+    }
+
+    /// 检查结果的行数是否超过 max_rows
+    pub(crate) fn check_rows(&self, rows: usize) -> Result<(), LimitError> {
+        match self.max_rows {
+            Some(limit) if rows > limit => Err(LimitError::TooManyRows { rows, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 按列估算 DataFrame 的大小：字符串取实际长度，其他类型按每个值的宽度
+fn estimated_bytes(df: &DataFrame) -> u64 {
+    df.get_columns()
+        .iter()
+        .map(|s| match s.dtype() {
+            DataType::Utf8 => s
+                .utf8()
+                .map(|ca| ca.into_iter().flatten().map(|v| v.len() as u64).sum())
+                .unwrap_or_default(),
+            DataType::Boolean | DataType::Int8 | DataType::UInt8 => s.len() as u64,
+            DataType::Int16 | DataType::UInt16 => s.len() as u64 * 2,
+            DataType::Int32 | DataType::UInt32 | DataType::Float32 | DataType::Date32 => {
+                s.len() as u64 * 4
+            }
+            _ => s.len() as u64 * 8,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_source_should_work() {
+        let options = QueryOptions {
+            allowed_schemes: Some(vec!["https".into(), "file".into()]),
+            allowed_hosts: Some(vec!["githubusercontent.com".into()]),
+            ..Default::default()
+        };
+        assert!(options
+            .check_source("https://raw.githubusercontent.com/a.csv")
+            .is_ok());
+        assert!(options.check_source("file:///tmp/a.csv").is_ok());
+        assert_eq!(
+            options.check_source("http://raw.githubusercontent.com/a.csv"),
+            Err(LimitError::NotAllowed(
+                "http://raw.githubusercontent.com/a.csv".into()
+            ))
+        );
+        assert!(options.check_source("https://evil.com/a.csv").is_err());
+        assert!(options
+            .check_source("https://githubusercontent.com.evil.com/a.csv")
+            .is_err());
+
+        assert!(QueryOptions::default().check_source("anything").is_ok());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::DataSet;
+use anyhow::{anyhow, Result};
+use parquet::file::writer::InMemoryWriteableCursor;
+use polars::prelude::*;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// 查询结果的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    Parquet,
+    /// Arrow IPC 文件格式
+    Arrow,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" => Ok(OutputFormat::Arrow),
+            v => Err(anyhow!("Output type {} not supported", v)),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// HTTP 响应的 Content-Type
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "text/csv",
+            OutputFormat::Json => "application/json",
+            OutputFormat::Parquet => "application/vnd.apache.parquet",
+            OutputFormat::Arrow => "application/vnd.apache.arrow.file",
+        }
+    }
+}
+
+impl DataSet {
+    /// 把 DataSet 按指定格式写入文件
+    pub fn write_to_file(&self, path: impl AsRef<Path>, format: OutputFormat) -> Result<()> {
+        self.write(File::create(path)?, format)
+    }
+
+    /// 把 DataSet 按指定格式写入 writer
+    pub fn write(&self, mut writer: impl Write, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Csv => CsvWriter::new(writer).finish(self)?,
+            OutputFormat::Json => writer.write_all(self.to_json()?.as_bytes())?,
+            OutputFormat::Parquet => {
+                // polars 的 ParquetWriter 需要可以 Seek 和 clone 的 writer，先写到内存里
+                let cursor = InMemoryWriteableCursor::default();
+                ParquetWriter::new(cursor.clone()).finish(self)?;
+                writer.write_all(&cursor.data())?
+            }
+            OutputFormat::Arrow => IpcWriter::new(writer).finish(self)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_path;
+
+    #[test]
+    fn write_to_file_should_work() {
+        let df = DataFrame::new(vec![Series::new("a", &[1i64, 2, 3])]).unwrap();
+        let path = temp_path("output.csv");
+        DataSet(df.clone())
+            .write_to_file(&path, "csv".parse().unwrap())
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\n1\n2\n3\n");
+
+        let mut buf = Vec::new();
+        DataSet(df.clone())
+            .write(&mut buf, OutputFormat::Json)
+            .unwrap();
+        assert_eq!(buf, br#"[{"a":1},{"a":2},{"a":3}]"#);
+
+        let mut buf = Vec::new();
+        DataSet(df).write(&mut buf, OutputFormat::Parquet).unwrap();
+        assert!(buf.starts_with(b"PAR1") && buf.ends_with(b"PAR1"));
+
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+/// SQL 参数的值，执行前会替换掉 SQL 中对应的 `${name}` 占位符
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Null,
+    Bool(bool),
+    /// 整数单独保存，超过 2^53 的值转换成 f64 会丢失精度
+    Integer(i64),
+    Number(f64),
+    Text(String),
+}
+
+/// 把参数转换成 SQL 字面量
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Param::Null => write!(f, "NULL"),
+            Param::Bool(v) => write!(f, "{}", v),
+            // 负数加上括号，避免 `a - ${x}` 变成 `a --5` 这样的注释
+            Param::Integer(v) if *v < 0 => write!(f, "({})", v),
+            Param::Integer(v) => write!(f, "{}", v),
+            Param::Number(v) if *v < 0.0 => write!(f, "({})", v),
+            Param::Number(v) => write!(f, "{}", v),
+            // 字符串里的单引号需要转义
+            Param::Text(v) => write!(f, "'{}'", v.replace('\'', "''")),
+        }
+    }
+}
+
+/// 把 SQL 中的 `${name}` 替换成参数的字面量，找不到参数时报错。
+/// 单引号的字符串和注释里的 `${name}` 原样保留
+pub fn bind_params(sql: &str, params: &HashMap<String, Param>) -> Result<String> {
+    let mut result = String::with_capacity(sql.len());
+    let mut rest = sql;
+    while let Some(start) = find_placeholder(rest) {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed placeholder in sql: {}", &rest[start..]))?;
+        let name = &rest[start + 2..start + end];
+        match params.get(name) {
+            Some(Param::Number(v)) if !v.is_finite() => {
+                return Err(anyhow!("Parameter {} should be a finite number", name))
+            }
+            Some(v) => result.push_str(&v.to_string()),
+            None => return Err(anyhow!("Missing value for parameter {}", name)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// 找到字符串和注释之外的第一个 `${`，注释的识别和 script::split 一样。
+/// 字符串里的 '' 相当于结束之后马上又开始，不需要特殊处理
+fn find_placeholder(sql: &str) -> Option<usize> {
+    let mut chars = sql.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' => {
+                for (_, v) in chars.by_ref() {
+                    if v == '\'' {
+                        break;
+                    }
+                }
+            }
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                for (_, v) in chars.by_ref() {
+                    if v == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut star = false;
+                for (_, v) in chars.by_ref() {
+                    if star && v == '/' {
+                        break;
+                    }
+                    star = v == '*';
+                }
+            }
+            '$' if matches!(chars.peek(), Some((_, '{'))) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_params_should_work() {
+        let params = HashMap::from([
+            ("deaths".to_owned(), Param::Number(500.0)),
+            ("name".to_owned(), Param::Text("Cote d'Ivoire".to_owned())),
+        ]);
+        let sql = bind_params(
+            "SELECT * FROM t WHERE new_deaths >= ${deaths} AND location = ${name}",
+            &params,
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "SELECT * FROM t WHERE new_deaths >= 500 AND location = 'Cote d''Ivoire'"
+        );
+
+        assert!(bind_params("SELECT * FROM t WHERE a = ${b}", &params).is_err());
+
+        // 字符串里的占位符不替换，负数加上括号
+        let params = HashMap::from([("x".to_owned(), Param::Number(-5.0))]);
+        assert_eq!(
+            bind_params("SELECT * FROM t WHERE a-${x} > 0 AND b = '${x}'", &params).unwrap(),
+            "SELECT * FROM t WHERE a-(-5) > 0 AND b = '${x}'"
+        );
+        // 注释里的占位符也不替换，没有值也不报错
+        assert_eq!(
+            bind_params(
+                "SELECT * FROM t -- ${y}\nWHERE a = ${x} /* ${y} */",
+                &params
+            )
+            .unwrap(),
+            "SELECT * FROM t -- ${y}\nWHERE a = (-5) /* ${y} */"
+        );
+        let params = HashMap::from([("x".to_owned(), Param::Number(f64::NAN))]);
+        assert!(bind_params("SELECT * FROM t WHERE a = ${x}", &params).is_err());
+    }
+}
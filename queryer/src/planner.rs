@@ -0,0 +1,510 @@
+use crate::convert::{collect_columns, column_name, is_portable, Expression, Relation, Sql};
+use crate::database;
+use crate::pushdown::Pushdown;
+use crate::{DataSet, Session};
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use sqlparser::ast::{Expr as SqlExpr, Ident};
+use std::collections::HashMap;
+
+/// 查询计划：每个数据源读哪些列、哪些条件在读取时就执行，之后再依次 join。
+/// 下推的条件只是为了少读数据，join 之后还会完整地执行一遍 WHERE
+#[derive(Debug)]
+pub(crate) struct Plan<'a> {
+    scans: Vec<Scan<'a>>,
+    /// joins[i] 把 scans[i + 1] 连接到前面的结果上
+    joins: Vec<JoinStep>,
+    /// 在 join 之后执行的 WHERE / ORDER BY / LIMIT，只用于 EXPLAIN
+    residual: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug)]
+struct Scan<'a> {
+    relation: Relation<'a>,
+    /// 数据库类型的数据源会把它转换成 SQL，其它数据源只用其中的 columns
+    pushdown: Pushdown,
+    /// 只涉及这个数据源的条件，不是数据库的话在读入之后、join 之前执行
+    filters: Vec<SqlExpr>,
+}
+
+#[derive(Debug)]
+struct JoinStep {
+    kind: JoinType,
+    on: Vec<(String, String)>,
+}
+
+impl<'a> Plan<'a> {
+    pub(crate) fn new(sql: &Sql<'a>) -> Self {
+        let relation = Relation {
+            source: sql.source,
+            alias: sql.alias.clone(),
+            reshape: sql.reshape.clone(),
+            sample: sql.sample.clone(),
+        };
+
+        let mut residual = Vec::new();
+        if !sql.conjuncts.is_empty() {
+            let conditions: Vec<String> = sql.conjuncts.iter().map(|v| v.to_string()).collect();
+            residual.push(("filter", conditions.join(" AND ")));
+        }
+        if !sql.order_by.is_empty() {
+            let orders: Vec<String> = sql
+                .order_by
+                .iter()
+                .map(|(name, desc, nulls_first)| {
+                    let nulls = match nulls_first {
+                        Some(true) => " NULLS FIRST",
+                        Some(false) => " NULLS LAST",
+                        None => "",
+                    };
+                    format!("{} {}{}", name, if *desc { "DESC" } else { "ASC" }, nulls)
+                })
+                .collect();
+            residual.push(("sort", orders.join(", ")));
+        }
+        if sql.offset.is_some() || sql.limit.is_some() {
+            let limit = sql.limit.filter(|v| *v != usize::MAX);
+            let detail = format!(
+                "offset {}, limit {}",
+                sql.offset.unwrap_or(0),
+                limit.map_or("none".to_owned(), |v| v.to_string())
+            );
+            residual.push(("limit", detail));
+        }
+
+        // 只有一个数据源时，整个 WHERE / ORDER BY / LIMIT 都可以尝试下推；
+        // pivot / unpivot 之后列变了，什么都不能下推；抽样要在这些之前，只能下推列
+        if sql.joins.is_empty() {
+            let pushdown = match (&relation.reshape, &relation.sample) {
+                (Some(_), _) => Pushdown::default(),
+                (None, Some(_)) => Pushdown::default().with_columns(sql.columns.clone()),
+                (None, None) => sql.pushdown().with_columns(sql.columns.clone()),
+            };
+            return Self {
+                scans: vec![Scan {
+                    relation,
+                    pushdown,
+                    filters: Vec::new(),
+                }],
+                joins: Vec::new(),
+                residual,
+            };
+        }
+
+        let relations: Vec<Relation> = std::iter::once(relation)
+            .chain(sql.joins.iter().map(|j| j.relation.clone()))
+            .collect();
+        // relations 之后会被消耗掉，别名先复制出来
+        let names: Vec<String> = relations.iter().filter_map(|r| r.alias.clone()).collect();
+        let aliases: Vec<&str> = names.iter().map(|v| v.as_str()).collect();
+        let kinds: Vec<JoinType> = sql.joins.iter().map(|j| j.kind).collect();
+
+        // 有没带表名的列时，不知道它属于哪个数据源，所有数据源都读取全部的列
+        let qualified = sql.columns.as_ref().filter(|columns| {
+            columns
+                .iter()
+                .all(|name| qualifier(name, &aliases).is_some())
+        });
+
+        let scans = relations
+            .into_iter()
+            .enumerate()
+            .map(|(i, relation)| {
+                let alias = relation.alias.clone().unwrap_or_default();
+                let columns = qualified.map(|columns| {
+                    columns
+                        .iter()
+                        .filter_map(|name| match qualifier(name, &aliases) {
+                            Some((a, column)) if a == alias => Some(column.to_owned()),
+                            _ => None,
+                        })
+                        .collect()
+                });
+
+                // outer join 中可能被补上空值的一边，提前过滤会改变结果
+                let nullable = (i > 0 && kinds[i - 1] != JoinType::Inner)
+                    || kinds[i..].contains(&JoinType::Outer);
+                let filters: Vec<SqlExpr> = match nullable {
+                    true => Vec::new(),
+                    false => sql
+                        .conjuncts
+                        .iter()
+                        .filter(|expr| is_portable(expr) && belongs_to(expr, &alias, &aliases))
+                        .map(|expr| unqualify(expr, &alias))
+                        .collect(),
+                };
+
+                let mut pushdown = Pushdown::default();
+                if relation.reshape.is_none() {
+                    pushdown = pushdown.with_columns(columns);
+                }
+                if !filters.is_empty() && relation.reshape.is_none() && relation.sample.is_none() {
+                    let conditions: Vec<String> =
+                        filters.iter().map(|v| format!("({})", v)).collect();
+                    pushdown.filter = Some(conditions.join(" AND "));
+                }
+                Scan {
+                    relation,
+                    pushdown,
+                    filters,
+                }
+            })
+            .collect();
+
+        let joins = sql
+            .joins
+            .iter()
+            .map(|j| JoinStep {
+                kind: j.kind,
+                on: j.on.clone(),
+            })
+            .collect();
+
+        Self {
+            scans,
+            joins,
+            residual,
+        }
+    }
+
+    /// 读入所有数据源并 join 起来。有 join 时，每一列都以 `别名.列名` 命名，
+    /// 在所有数据源中不重复的列名也可以直接使用
+    pub(crate) async fn execute(&self, session: &Session) -> Result<DataFrame> {
+        if self.joins.is_empty() {
+            return self.scans[0].fetch(session).await;
+        }
+
+        let mut frames = Vec::with_capacity(self.scans.len());
+        for scan in &self.scans {
+            frames.push(scan.load(session).await?);
+        }
+
+        // 统计每个列名出现的次数，只出现一次的列名加一个不带表名的副本
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for df in &frames {
+            for name in df.get_column_names() {
+                *counts.entry(name.to_owned()).or_default() += 1;
+            }
+        }
+        let frames = self
+            .scans
+            .iter()
+            .zip(frames)
+            .map(|(scan, df)| {
+                let mut columns = Vec::with_capacity(df.width() * 2);
+                for s in df.get_columns() {
+                    let mut qualified = s.clone();
+                    qualified.rename(&format!("{}.{}", scan.alias(), s.name()));
+                    columns.push(qualified);
+                    if counts.get(s.name()) == Some(&1) {
+                        columns.push(s.clone());
+                    }
+                }
+                DataFrame::new(columns)
+            })
+            .collect::<Result<Vec<_>, PolarsError>>()?;
+
+        let mut frames = frames.into_iter();
+        let mut result = frames.next().unwrap();
+        for (i, (join, right)) in self.joins.iter().zip(frames).enumerate() {
+            result = join.apply(i, result, right)?;
+        }
+        Ok(result)
+    }
+
+    /// 有 join 时，把 SELECT * 展开成所有带表名的列，避免重复
+    pub(crate) fn expand(&self, selection: Vec<Expr>, df: &DataFrame) -> Vec<Expr> {
+        if self.joins.is_empty() {
+            return selection;
+        }
+        let aliases = self.aliases();
+        selection
+            .into_iter()
+            .flat_map(|expr| match expr {
+                Expr::Wildcard => df
+                    .get_column_names()
+                    .into_iter()
+                    .filter(|name| qualifier(name, &aliases).is_some())
+                    .map(col)
+                    .collect(),
+                expr => vec![expr],
+            })
+            .collect()
+    }
+
+    /// 有 join 时，结果中的 `别名.列名` 在不重复的情况下去掉表名
+    pub(crate) fn unqualify(&self, df: DataFrame) -> Result<DataFrame> {
+        if self.joins.is_empty() {
+            return Ok(df);
+        }
+        let aliases = self.aliases();
+        let names: Vec<String> = df
+            .get_column_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let columns = df
+            .get_columns()
+            .iter()
+            .map(|s| {
+                let mut s = s.clone();
+                if let Some((_, column)) = qualifier(s.name(), &aliases) {
+                    let bare = names
+                        .iter()
+                        .filter(|v| qualifier(v, &aliases).map_or(v.as_str(), |q| q.1) == column)
+                        .count();
+                    if bare == 1 {
+                        let column = column.to_owned();
+                        s.rename(&column);
+                    }
+                }
+                s
+            })
+            .collect();
+        Ok(DataFrame::new(columns)?)
+    }
+
+    /// EXPLAIN 的结果：每一步是什么、作用在哪里、下推了什么
+    pub(crate) fn explain(&self, session: &Session) -> Result<DataSet> {
+        let mut steps = Vec::new();
+        let mut targets = Vec::new();
+        let mut details = Vec::new();
+        for scan in &self.scans {
+            let source = session.resolve(scan.relation.source)?;
+            steps.push("scan".to_owned());
+            targets.push(match &scan.relation.alias {
+                Some(alias) if alias != source => format!("{} AS {}", source, alias),
+                _ => source.to_owned(),
+            });
+            details.push(scan.describe(source));
+        }
+        for (join, scan) in self.joins.iter().zip(&self.scans[1..]) {
+            let on: Vec<String> = join
+                .on
+                .iter()
+                .map(|(l, r)| format!("{} = {}", l, r))
+                .collect();
+            steps.push("join".to_owned());
+            targets.push(scan.alias().to_owned());
+            details.push(format!("{} ON {}", join.name(), on.join(" AND ")));
+        }
+        for (step, detail) in &self.residual {
+            steps.push(step.to_string());
+            targets.push(String::new());
+            details.push(detail.to_owned());
+        }
+        Ok(DataSet(DataFrame::new(vec![
+            Series::new("step", steps),
+            Series::new("target", targets),
+            Series::new("detail", details),
+        ])?))
+    }
+
+    fn aliases(&self) -> Vec<&str> {
+        self.scans.iter().map(|v| v.alias()).collect()
+    }
+}
+
+impl<'a> Scan<'a> {
+    fn alias(&self) -> &str {
+        self.relation.alias.as_deref().unwrap_or_default()
+    }
+
+    /// 读入数据源，有抽样的话马上抽样，之后再做 pivot / unpivot
+    async fn fetch(&self, session: &Session) -> Result<DataFrame> {
+        let mut df = session.scan(self.relation.source, &self.pushdown).await?.0;
+        if let Some(sample) = &self.relation.sample {
+            df = sample.apply(df)?;
+        }
+        match &self.relation.reshape {
+            Some(reshape) => reshape.apply(df),
+            None => Ok(df),
+        }
+    }
+
+    async fn load(&self, session: &Session) -> Result<DataFrame> {
+        let df = self.fetch(session).await?;
+        if self.pushed(session.resolve(self.relation.source)?) || self.filters.is_empty() {
+            return Ok(df);
+        }
+        let mut lazy = df.lazy();
+        for expr in &self.filters {
+            lazy = lazy.filter(Expression(Box::new(expr.to_owned())).try_into()?);
+        }
+        Ok(lazy.collect()?)
+    }
+
+    /// 条件是否已经下推到数据库中执行
+    fn pushed(&self, source: &str) -> bool {
+        database::is_database(source) && self.pushdown.filter.is_some()
+    }
+
+    fn describe(&self, source: &str) -> String {
+        let mut detail = if database::is_database(source) {
+            match database::to_sql(source, &self.pushdown) {
+                Ok(sql) => format!("pushed into database: {}", sql),
+                Err(e) => e.to_string(),
+            }
+        } else {
+            match &self.pushdown.columns {
+                Some(columns) => format!("read columns: {}", columns.join(", ")),
+                None => "read all columns".to_owned(),
+            }
+        };
+        if let Some(sample) = &self.relation.sample {
+            detail.push_str(&format!("; {}", sample));
+        }
+        if let Some(reshape) = &self.relation.reshape {
+            detail.push_str(&format!("; {}", reshape));
+        }
+        if !self.filters.is_empty() && !self.pushed(source) {
+            let filters: Vec<String> = self.filters.iter().map(|v| v.to_string()).collect();
+            detail.push_str(&format!("; filter before join: {}", filters.join(" AND ")));
+        }
+        detail
+    }
+}
+
+impl JoinStep {
+    fn name(&self) -> &'static str {
+        match self.kind {
+            JoinType::Inner => "INNER",
+            JoinType::Left => "LEFT",
+            _ => "FULL",
+        }
+    }
+
+    /// 右边的连接键复制一份再 join，这样 polars 去掉连接键之后原来的列还在
+    fn apply(&self, step: usize, left: DataFrame, right: DataFrame) -> Result<DataFrame> {
+        let has = |df: &DataFrame, name: &str| df.get_column_names().contains(&name);
+        let mut right = right;
+        let mut left_on = Vec::with_capacity(self.on.len());
+        let mut right_on = Vec::with_capacity(self.on.len());
+        for (i, (a, b)) in self.on.iter().enumerate() {
+            let (l, r) = match (has(&left, a), has(&right, b), has(&left, b), has(&right, a)) {
+                (true, true, _, _) => (a, b),
+                (_, _, true, true) => (b, a),
+                _ => {
+                    return Err(anyhow!(
+                        "Join condition {} = {} should compare columns from both sides",
+                        a,
+                        b
+                    ))
+                }
+            };
+            let key = format!("__join_{}_{}", step, i);
+            let mut s = right.column(r)?.clone();
+            s.rename(&key);
+            right.with_column(s)?;
+            left_on.push(col(l));
+            right_on.push(col(&key));
+        }
+        Ok(left
+            .lazy()
+            .join(right.lazy(), left_on, right_on, self.kind)
+            .collect()?)
+    }
+}
+
+/// `别名.列名` 拆成别名和列名，别名必须是查询中的数据源
+fn qualifier<'b>(name: &'b str, aliases: &[&str]) -> Option<(&'b str, &'b str)> {
+    aliases.iter().find_map(|alias| {
+        name.strip_prefix(alias)
+            .and_then(|v| v.strip_prefix('.'))
+            .map(|column| (&name[..alias.len()], column))
+    })
+}
+
+/// 条件中所有的列都属于 alias
+fn belongs_to(expr: &SqlExpr, alias: &str, aliases: &[&str]) -> bool {
+    let mut columns = Vec::new();
+    collect_columns(expr, &mut columns)
+        && !columns.is_empty()
+        && columns
+            .iter()
+            .all(|name| matches!(qualifier(name, aliases), Some((a, _)) if a == alias))
+}
+
+/// 去掉条件中列名前面的表名，之后在数据源上执行
+fn unqualify(expr: &SqlExpr, alias: &str) -> SqlExpr {
+    match expr {
+        SqlExpr::Identifier(_) | SqlExpr::CompoundIdentifier(_) => {
+            let name = column_name(expr).unwrap_or_default();
+            let column = qualifier(&name, &[alias]).map_or(name.as_str(), |v| v.1);
+            SqlExpr::Identifier(Ident::new(column))
+        }
+        SqlExpr::BinaryOp { left, op, right } => SqlExpr::BinaryOp {
+            left: Box::new(unqualify(left, alias)),
+            op: op.clone(),
+            right: Box::new(unqualify(right, alias)),
+        },
+        SqlExpr::IsNull(v) => SqlExpr::IsNull(Box::new(unqualify(v, alias))),
+        SqlExpr::IsNotNull(v) => SqlExpr::IsNotNull(Box::new(unqualify(v, alias))),
+        SqlExpr::Nested(v) => SqlExpr::Nested(Box::new(unqualify(v, alias))),
+        v => v.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::temp_source;
+    use crate::{query, Session};
+
+    #[tokio::test]
+    async fn join_should_work() {
+        let mut session = Session::new();
+        session.register(
+            "pets",
+            temp_source("pets.csv", "id,name,owner\n1,tom,1\n2,jerry,2\n3,spike,1\n"),
+        );
+        session.register(
+            "owners",
+            temp_source("owners.csv", "id,name,age\n1,alice,30\n2,bob,8\n"),
+        );
+
+        let ds = session
+            .query(
+                "SELECT p.name, o.name AS owner FROM pets AS p JOIN owners AS o ON p.owner = o.id \
+                WHERE o.age > 10 ORDER BY p.name",
+            )
+            .await
+            .unwrap();
+        assert_eq!(ds.to_csv().unwrap(), "name,owner\nspike,alice\ntom,alice\n");
+
+        // 不重复的列名可以不带表名，SELECT * 的列名在不冲突时也去掉表名
+        let ds = session
+            .query("SELECT * FROM pets JOIN owners ON owner = owners.id WHERE age < 10")
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 1);
+        assert!(ds.column("age").is_ok());
+        assert!(ds.column("pets.name").is_ok());
+    }
+
+    #[tokio::test]
+    async fn explain_should_show_pushdown() {
+        let pets = temp_source("explain.csv", "id,name,owner\n1,tom,1\n");
+        let sql = format!(
+            "EXPLAIN SELECT p.name FROM {} AS p JOIN {} AS o ON p.owner = o.id \
+            WHERE p.id > 0 AND o.id < 5",
+            pets, pets
+        );
+        let ds = query(sql).await.unwrap();
+        let details: Vec<_> = ds
+            .column("detail")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(
+            details[..3],
+            [
+                "read columns: id, name, owner; filter before join: id > 0",
+                "read columns: id; filter before join: id < 5",
+                "INNER ON p.owner = o.id",
+            ]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::database::quote_identifier;
+
+/// 可以下推到数据源执行的查询条件。数据源不支持时可以忽略，
+/// polars 之后还会再执行一遍 WHERE / ORDER BY / LIMIT，结果是一样的
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Pushdown {
+    /// 原始的 WHERE 条件
+    pub(crate) filter: Option<String>,
+    /// (列名, 是否降序, NULLS FIRST / NULLS LAST)
+    pub(crate) order_by: Vec<(String, bool, Option<bool>)>,
+    /// 最多需要的行数，已经包含了 OFFSET
+    pub(crate) limit: Option<usize>,
+    /// 需要读取的列，None 表示全部
+    pub(crate) columns: Option<Vec<String>>,
+}
+
+impl Pushdown {
+    pub(crate) fn new(
+        filter: Option<String>,
+        order_by: &[(String, bool, Option<bool>)],
+        offset: Option<i64>,
+        limit: Option<usize>,
+    ) -> Self {
+        // LIMIT 解析失败时是 usize::MAX，相当于没有限制
+        let limit = limit
+            .filter(|v| *v != usize::MAX)
+            .map(|v| v.saturating_add(offset.unwrap_or(0).max(0) as usize));
+        Self {
+            filter,
+            order_by: order_by.to_vec(),
+            limit,
+            columns: None,
+        }
+    }
+
+    /// 只读取 columns 中的列
+    pub(crate) fn with_columns(mut self, columns: Option<Vec<String>>) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// 生成在数据源上执行的 SQL，relation 是表名或者子查询
+    pub(crate) fn to_sql(&self, relation: &str) -> String {
+        let columns = match &self.columns {
+            Some(v) => v
+                .iter()
+                .map(|v| quote_identifier(v))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "*".to_owned(),
+        };
+        let mut sql = format!("SELECT {} FROM {}", columns, relation);
+        if let Some(filter) = &self.filter {
+            sql.push_str(&format!(" WHERE {}", filter));
+        }
+        if !self.order_by.is_empty() {
+            let orders: Vec<String> = self
+                .order_by
+                .iter()
+                .map(|(name, desc, nulls_first)| {
+                    let mut order = name.to_owned();
+                    if *desc {
+                        order.push_str(" DESC");
+                    }
+                    match nulls_first {
+                        Some(true) => order.push_str(" NULLS FIRST"),
+                        Some(false) => order.push_str(" NULLS LAST"),
+                        None => {}
+                    }
+                    order
+                })
+                .collect();
+            sql.push_str(&format!(" ORDER BY {}", orders.join(", ")));
+        }
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushdown_to_sql_should_work() {
+        let pushdown = Pushdown::new(
+            Some("age > 5".into()),
+            &[("age".into(), true, Some(false))],
+            Some(10),
+            Some(5),
+        );
+        assert_eq!(
+            pushdown.to_sql("\"pets\""),
+            "SELECT * FROM \"pets\" WHERE age > 5 ORDER BY age DESC NULLS LAST LIMIT 15"
+        );
+        assert_eq!(Pushdown::default().to_sql("t"), "SELECT * FROM t");
+
+        let pushdown = Pushdown::default().with_columns(Some(vec!["id".into(), "name".into()]));
+        assert_eq!(pushdown.to_sql("t"), "SELECT \"id\", \"name\" FROM t");
+    }
+}
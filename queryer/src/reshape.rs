@@ -0,0 +1,316 @@
+use crate::summary::is_numeric;
+use anyhow::{anyhow, Result};
+use polars::prelude::*;
+use sqlparser::ast::{Expr as SqlExpr, FunctionArg, ObjectName, Value as SqlValue};
+use std::fmt;
+
+/// FROM 中对数据源做的行列转换，读入数据之后、执行查询之前进行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reshape {
+    /// pivot(source, sum(amount), region, 'east', 'west')，相当于
+    /// `PIVOT (sum(amount) FOR region IN ('east', 'west'))`：
+    /// 其它列作为分组，region 的每个值变成一列，值是 amount 的聚合
+    Pivot {
+        aggregation: String,
+        value: String,
+        column: String,
+        values: Vec<String>,
+    },
+    /// unpivot(source, amount, quarter, q1, q2)，相当于
+    /// `UNPIVOT (amount FOR quarter IN (q1, q2))`：
+    /// q1、q2 变成 quarter 和 amount 两列，其它列保留，值为 NULL 的行被去掉
+    Unpivot {
+        value: String,
+        name: String,
+        columns: Vec<String>,
+    },
+}
+
+/// pivot 支持的聚合函数
+const AGGREGATIONS: [&str; 8] = [
+    "sum", "min", "max", "avg", "mean", "median", "count", "first",
+];
+
+impl Reshape {
+    /// 识别 pivot(...) / unpivot(...) 表函数，返回数据源和转换
+    pub(crate) fn parse<'a>(
+        name: &ObjectName,
+        args: &'a [FunctionArg],
+    ) -> Result<Option<(&'a str, Self)>> {
+        let function = match name.0.as_slice() {
+            [v] => v.value.to_lowercase(),
+            _ => return Ok(None),
+        };
+        if function != "pivot" && function != "unpivot" {
+            return Ok(None);
+        }
+        let usage = || match function.as_str() {
+            "pivot" => anyhow!("pivot expects (source, sum(value), column, 'v1', 'v2', ...)"),
+            _ => anyhow!("unpivot expects (source, value, name, column1, column2, ...)"),
+        };
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                FunctionArg::Unnamed(expr) => Ok(expr),
+                FunctionArg::Named { .. } => Err(usage()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if args.len() < 4 {
+            return Err(usage());
+        }
+
+        // 数据源可以是表名、url，或者 read_url 那样的字符串
+        let source = match args[0] {
+            SqlExpr::Identifier(id) => id.value.as_str(),
+            SqlExpr::Value(SqlValue::SingleQuotedString(v)) => v.as_str(),
+            _ => return Err(usage()),
+        };
+        let identifier = |expr: &SqlExpr| match expr {
+            SqlExpr::Identifier(id) => Ok(id.value.clone()),
+            _ => Err(usage()),
+        };
+
+        let reshape = if function == "pivot" {
+            let (aggregation, value) = match args[1] {
+                SqlExpr::Function(f) if f.args.len() == 1 => match &f.args[0] {
+                    FunctionArg::Unnamed(expr) => {
+                        (f.name.to_string().to_lowercase(), identifier(expr)?)
+                    }
+                    _ => return Err(usage()),
+                },
+                _ => return Err(usage()),
+            };
+            if !AGGREGATIONS.contains(&aggregation.as_str()) {
+                return Err(anyhow!(
+                    "Aggregation {} is not supported in pivot",
+                    aggregation
+                ));
+            }
+            let values = args[3..]
+                .iter()
+                .map(|expr| match expr {
+                    SqlExpr::Value(SqlValue::SingleQuotedString(v)) => Ok(v.to_owned()),
+                    SqlExpr::Value(SqlValue::Number(v, _)) => Ok(v.to_owned()),
+                    SqlExpr::Identifier(id) => Ok(id.value.clone()),
+                    _ => Err(usage()),
+                })
+                .collect::<Result<_>>()?;
+            Reshape::Pivot {
+                aggregation,
+                value,
+                column: identifier(args[2])?,
+                values,
+            }
+        } else {
+            Reshape::Unpivot {
+                value: identifier(args[1])?,
+                name: identifier(args[2])?,
+                columns: args[3..]
+                    .iter()
+                    .map(|v| identifier(v))
+                    .collect::<Result<_>>()?,
+            }
+        };
+        Ok(Some((source, reshape)))
+    }
+
+    pub(crate) fn apply(&self, df: DataFrame) -> Result<DataFrame> {
+        match self {
+            Reshape::Pivot {
+                aggregation,
+                value,
+                column,
+                values,
+            } => pivot(df, aggregation, value, column, values),
+            Reshape::Unpivot {
+                value,
+                name,
+                columns,
+            } => unpivot(df, value, name, columns),
+        }
+    }
+}
+
+/// 对 IN 中的每个值，只聚合 column 等于这个值的行，结果作为以这个值命名的列
+fn pivot(
+    df: DataFrame,
+    aggregation: &str,
+    value: &str,
+    column: &str,
+    values: &[String],
+) -> Result<DataFrame> {
+    df.column(value)?;
+    df.column(column)?;
+    let keys: Vec<Expr> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|name| *name != value && *name != column)
+        .map(col)
+        .collect();
+    let mut exprs = Vec::with_capacity(values.len() * 2);
+    for v in values {
+        let selected = col(value).filter(col(column).cast(DataType::Utf8).eq(lit(v.as_str())));
+        let expr = match aggregation {
+            "sum" => {
+                // 没有对应的行时 sum 是 0，和 SQL 一样应该是空值，需要行数来判断
+                exprs.push(selected.clone().count().alias(&count_name(v)));
+                selected.sum()
+            }
+            "min" => selected.min(),
+            "max" => selected.max(),
+            "avg" | "mean" => selected.mean(),
+            "median" => selected.median(),
+            "count" => selected.count(),
+            _ => selected.first(),
+        };
+        exprs.push(expr.alias(v));
+    }
+    let lazy = df.lazy();
+    let mut df = match keys.is_empty() {
+        true => lazy.select(exprs).collect()?,
+        false => lazy.groupby(keys).agg(exprs).collect()?,
+    };
+    if aggregation == "sum" {
+        for v in values {
+            let count = df.drop_in_place(&count_name(v))?;
+            let s = df.column(v)?;
+            let nulls = Int32Chunked::full_null(v, s.len())
+                .into_series()
+                .cast_with_dtype(s.dtype())?;
+            let s = s.zip_with(&count.gt(0), &nulls)?;
+            df.with_column(s)?;
+        }
+    }
+    Ok(df)
+}
+
+fn count_name(value: &str) -> String {
+    format!("__pivot_count:{}", value)
+}
+
+/// 把 columns 转换成 name / value 两列，值的类型不一致时统一转换
+fn unpivot(df: DataFrame, value: &str, name: &str, columns: &[String]) -> Result<DataFrame> {
+    let mut df = df;
+    let dtypes = columns
+        .iter()
+        .map(|v| Ok(df.column(v)?.dtype().clone()))
+        .collect::<Result<Vec<_>>>()?;
+    if dtypes.iter().any(|v| *v != dtypes[0]) {
+        let dtype = match dtypes.iter().all(is_numeric) {
+            true => DataType::Float64,
+            false => DataType::Utf8,
+        };
+        for v in columns {
+            let s = df.column(v)?.cast_with_dtype(&dtype)?;
+            df.with_column(s)?;
+        }
+    }
+
+    let ids: Vec<&str> = df
+        .get_column_names()
+        .into_iter()
+        .filter(|v| !columns.iter().any(|c| c == v))
+        .collect();
+    let columns: Vec<&str> = columns.iter().map(|v| v.as_str()).collect();
+    let mut df = df.melt(ids, columns)?;
+    // melt 的结果固定叫 variable / value，先挪开 value，免得和 name 冲突
+    df.rename("value", "__unpivot_value")?;
+    df.rename("variable", name)?;
+    df.rename("__unpivot_value", value)?;
+    Ok(df.lazy().filter(col(value).is_not_null()).collect()?)
+}
+
+/// EXPLAIN 中显示的转换
+impl fmt::Display for Reshape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reshape::Pivot {
+                aggregation,
+                value,
+                column,
+                values,
+            } => write!(
+                f,
+                "pivot {}({}) FOR {} IN ({})",
+                aggregation,
+                value,
+                column,
+                values.join(", ")
+            ),
+            Reshape::Unpivot {
+                value,
+                name,
+                columns,
+            } => write!(
+                f,
+                "unpivot {} FOR {} IN ({})",
+                value,
+                name,
+                columns.join(", ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query;
+    use crate::testing::temp_source;
+    use polars::prelude::TakeRandom;
+
+    #[tokio::test]
+    async fn pivot_should_work() {
+        let source = temp_source(
+            "pivot.csv",
+            "year,region,amount\n2020,east,1\n2020,west,2\n2020,east,3\n2021,west,4\n",
+        );
+        let sql = format!(
+            "SELECT year, east, west FROM pivot({}, sum(amount), region, 'east', 'west') \
+            ORDER BY year",
+            source
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        assert_eq!(ds.column("east").unwrap().i64().unwrap().get(0), Some(4));
+        let west: Vec<_> = ds
+            .column("west")
+            .unwrap()
+            .i64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(west, [Some(2), Some(4)]);
+        // 2021 年没有 east 的行，结果是空值而不是 0
+        assert_eq!(ds.column("east").unwrap().i64().unwrap().get(1), None);
+
+        let sql = format!(
+            "SELECT * FROM pivot({}, stddev(amount), region, 'east')",
+            source
+        );
+        assert!(query(sql).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unpivot_should_work() {
+        let source = temp_source("unpivot.csv", "product,q1,q2\napple,10,20\npear,30,\n");
+        let sql = format!(
+            "SELECT product, quarter, amount FROM unpivot({}, amount, quarter, q1, q2) \
+            WHERE amount > 15 ORDER BY amount",
+            source
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        let quarters: Vec<_> = ds
+            .column("quarter")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(quarters, [Some("q2"), Some("q1")]);
+
+        // pear 的 q2 是空的，不出现在结果里
+        let sql = format!("SELECT * FROM unpivot({}, amount, quarter, q1, q2)", source);
+        assert_eq!(query(sql).await.unwrap().height(), 3);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Result;
+use polars::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sqlparser::ast::{Expr as SqlExpr, FunctionArg, Value as SqlValue};
+use sqlparser::parser::ParserError;
+use std::fmt;
+
+/// FROM 中的 TABLESAMPLE：读入数据源之后马上随机抽取一部分行
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sample {
+    pub(crate) size: SampleSize,
+    /// REPEATABLE (seed)，同样的 seed 和数据抽到同样的行
+    pub(crate) seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SampleSize {
+    /// BERNOULLI (1 PERCENT)：每一行以这个概率被选中
+    Percent(f64),
+    /// (1000 ROWS)：随机选出这么多行，不够的话全部保留
+    Rows(usize),
+}
+
+/// sqlparser 不认识 TABLESAMPLE，解析之前把它改写成 sqlparser 支持的 table hint：
+/// `t AS a TABLESAMPLE BERNOULLI (1 PERCENT) REPEATABLE (42)` 变成
+/// `t AS a WITH (tablesample(percent, 1, 42))`。SYSTEM 和 BERNOULLI 一样按行抽样
+pub(crate) fn rewrite(sql: &str) -> Result<String> {
+    const KEYWORD: &str = "TABLESAMPLE";
+    let mut result = String::with_capacity(sql.len());
+    let mut rest = sql;
+    while let Some(start) = find_keyword(rest, KEYWORD) {
+        result.push_str(&rest[..start]);
+        let mut cursor = Cursor(&rest[start + KEYWORD.len()..]);
+        result.push_str(&cursor.clause()?);
+        rest = cursor.0;
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// 找到引号之外、前后是空白或者括号的关键字
+fn find_keyword(sql: &str, keyword: &str) -> Option<usize> {
+    let mut chars = sql.char_indices();
+    let mut previous = ' ';
+    while let Some((i, c)) = chars.next() {
+        if matches!(c, '\'' | '"' | '`') {
+            for (_, v) in chars.by_ref() {
+                if v == c {
+                    break;
+                }
+            }
+        } else if (previous.is_whitespace() || previous == ')')
+            && sql[i..]
+                .get(..keyword.len())
+                .is_some_and(|v| v.eq_ignore_ascii_case(keyword))
+            && sql[i + keyword.len()..]
+                .chars()
+                .next()
+                .is_none_or(|v| v.is_whitespace() || v == '(')
+        {
+            return Some(i);
+        }
+        previous = c;
+    }
+    None
+}
+
+/// 解析 TABLESAMPLE 之后的部分，每次解析之后 self.0 是剩下的 SQL
+struct Cursor<'a>(&'a str);
+
+impl<'a> Cursor<'a> {
+    fn clause(&mut self) -> Result<String, ParserError> {
+        let mut word = self.word();
+        if word.eq_ignore_ascii_case("BERNOULLI") || word.eq_ignore_ascii_case("SYSTEM") {
+            word = self.word();
+        }
+        if !word.is_empty() {
+            return Err(error(&format!("unknown sampling method {}", word)));
+        }
+        self.expect('(')?;
+        let size = self.number()?;
+        let unit = self.word().to_uppercase();
+        self.expect(')')?;
+        let unit = match unit.as_str() {
+            "" | "PERCENT" => match size.parse::<f64>() {
+                Ok(v) if (0.0..=100.0).contains(&v) => "percent",
+                _ => return Err(error("percent should be between 0 and 100")),
+            },
+            "ROWS" => match size.parse::<usize>() {
+                Ok(_) => "rows",
+                Err(_) => return Err(error("rows should be an integer")),
+            },
+            v => return Err(error(&format!("unknown unit {}", v))),
+        };
+
+        let before = self.0;
+        let seed = match self.word() {
+            v if v.eq_ignore_ascii_case("REPEATABLE") => {
+                self.expect('(')?;
+                let seed = self.number()?;
+                self.expect(')')?;
+                seed.parse::<u64>()
+                    .map_err(|_| error("seed should be a non-negative integer"))?;
+                seed
+            }
+            _ => {
+                self.0 = before;
+                "NULL"
+            }
+        };
+        Ok(format!("WITH (tablesample({}, {}, {}))", unit, size, seed))
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.take(|c| c.is_ascii_alphabetic())
+    }
+
+    fn number(&mut self) -> Result<&'a str, ParserError> {
+        match self.take(|c| c.is_ascii_digit() || c == '.') {
+            "" => Err(error("expect a number")),
+            v => Ok(v),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParserError> {
+        self.0 = self.0.trim_start();
+        match self.0.strip_prefix(expected) {
+            Some(rest) => {
+                self.0 = rest;
+                Ok(())
+            }
+            None => Err(error(&format!("expect {}", expected))),
+        }
+    }
+
+    /// 跳过空白，取出满足 f 的一段
+    fn take(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let s = self.0.trim_start();
+        let end = s.find(|c| !f(c)).unwrap_or(s.len());
+        self.0 = &s[end..];
+        &s[..end]
+    }
+}
+
+fn error(message: &str) -> ParserError {
+    ParserError::ParserError(format!("Invalid TABLESAMPLE: {}", message))
+}
+
+impl Sample {
+    /// 从 rewrite 生成的 table hint 中取出抽样的参数
+    pub(crate) fn from_hints(hints: &[SqlExpr]) -> Option<Self> {
+        hints.iter().find_map(|hint| {
+            let f = match hint {
+                SqlExpr::Function(f) if f.name.to_string().eq_ignore_ascii_case("tablesample") => f,
+                _ => return None,
+            };
+            let args: Vec<&SqlExpr> = f
+                .args
+                .iter()
+                .filter_map(|arg| match arg {
+                    FunctionArg::Unnamed(expr) => Some(expr),
+                    _ => None,
+                })
+                .collect();
+            let (unit, size, seed) = match args.as_slice() {
+                [SqlExpr::Identifier(unit), SqlExpr::Value(SqlValue::Number(size, _)), seed] => {
+                    (unit, size, seed)
+                }
+                _ => return None,
+            };
+            let size = match unit.value.as_str() {
+                "percent" => SampleSize::Percent(size.parse().ok()?),
+                _ => SampleSize::Rows(size.parse().ok()?),
+            };
+            let seed = match seed {
+                SqlExpr::Value(SqlValue::Number(v, _)) => Some(v.parse().ok()?),
+                _ => None,
+            };
+            Some(Sample { size, seed })
+        })
+    }
+
+    /// 随机选出一部分行，保持原来的顺序
+    pub(crate) fn apply(&self, df: DataFrame) -> Result<DataFrame> {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let height = df.height();
+        let mask: BooleanChunked = match self.size {
+            SampleSize::Percent(p) => (0..height)
+                .map(|_| rng.gen_bool((p / 100.0).clamp(0.0, 1.0)))
+                .collect(),
+            SampleSize::Rows(n) => {
+                let mut selected = vec![false; height];
+                for i in rand::seq::index::sample(&mut rng, height, n.min(height)) {
+                    selected[i] = true;
+                }
+                selected.into_iter().collect()
+            }
+        };
+        Ok(df.filter(&mask)?)
+    }
+}
+
+/// EXPLAIN 中显示的抽样方式
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            SampleSize::Percent(v) => write!(f, "sample {}%", v)?,
+            SampleSize::Rows(v) => write!(f, "sample {} rows", v)?,
+        }
+        match self.seed {
+            Some(seed) => write!(f, " repeatable ({})", seed),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+    use crate::testing::temp_source;
+
+    #[test]
+    fn rewrite_should_work() {
+        assert_eq!(
+            rewrite("SELECT * FROM t AS a TABLESAMPLE BERNOULLI (1.5 PERCENT) REPEATABLE (42) WHERE x > 1")
+                .unwrap(),
+            "SELECT * FROM t AS a WITH (tablesample(percent, 1.5, 42)) WHERE x > 1"
+        );
+        assert_eq!(
+            rewrite("SELECT * FROM t tablesample (1000 rows)").unwrap(),
+            "SELECT * FROM t WITH (tablesample(rows, 1000, NULL))"
+        );
+        // 字符串和数据源地址里的 tablesample 不改写
+        let sql = "SELECT * FROM file:///tablesample.csv WHERE a = ' tablesample (1)'";
+        assert_eq!(rewrite(sql).unwrap(), sql);
+
+        assert!(rewrite("SELECT * FROM t TABLESAMPLE BERNOULLI (200)").is_err());
+        assert!(rewrite("SELECT * FROM t TABLESAMPLE RESERVOIR (10 ROWS)").is_err());
+    }
+
+    #[tokio::test]
+    async fn tablesample_should_work() {
+        let content: String = (0..1000).map(|i| format!("{}\n", i)).collect();
+        let source = temp_source("sample.csv", &format!("id\n{}", content));
+
+        let sql = format!(
+            "SELECT id FROM {} TABLESAMPLE (10 ROWS) REPEATABLE (7)",
+            source
+        );
+        let first = query(&sql).await.unwrap();
+        assert_eq!(first.height(), 10);
+        assert!(first.frame_equal(&query(&sql).await.unwrap()));
+
+        // 先抽样再过滤
+        let sql = format!(
+            "SELECT id FROM {} TABLESAMPLE BERNOULLI (50 PERCENT) REPEATABLE (1) WHERE id < 100",
+            source
+        );
+        let height = query(sql).await.unwrap().height();
+        assert!(height > 20 && height < 80);
+
+        let sql = format!("SELECT id FROM {} TABLESAMPLE (0 PERCENT)", source);
+        assert_eq!(query(sql).await.unwrap().height(), 0);
+    }
+}
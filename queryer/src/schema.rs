@@ -0,0 +1,84 @@
+use crate::{DataSet, Session};
+use anyhow::Result;
+use polars::prelude::*;
+
+/// DESCRIBE 时每列展示的样例数量
+const SAMPLE_SIZE: usize = 3;
+
+/// 数据源中某一列的信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    /// 列名
+    pub name: String,
+    /// 推断出来的数据类型
+    pub dtype: String,
+    /// 空值的数量
+    pub null_count: usize,
+    /// 前几个非空的值
+    pub samples: Vec<String>,
+}
+
+/// 获取数据源的 schema，不需要先写 SQL
+pub async fn schema(source: impl AsRef<str>) -> Result<Vec<ColumnInfo>> {
+    Session::default().schema(source.as_ref()).await
+}
+
+impl DataSet {
+    /// 获取每一列的列名、类型、空值数量和样例值
+    pub fn columns_info(&self) -> Vec<ColumnInfo> {
+        self.get_columns()
+            .iter()
+            .map(|s| {
+                let values = s.drop_nulls();
+                let samples = (0..values.len().min(SAMPLE_SIZE))
+                    .map(|i| values.get(i).to_string().trim_matches('"').to_owned())
+                    .collect();
+                ColumnInfo {
+                    name: s.name().to_owned(),
+                    dtype: s.dtype().to_string(),
+                    null_count: s.null_count(),
+                    samples,
+                }
+            })
+            .collect()
+    }
+}
+
+/// 把 schema 转换成 DataSet，作为 DESCRIBE 语句的结果
+pub(crate) fn to_dataset(columns: &[ColumnInfo]) -> Result<DataSet> {
+    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+    let dtypes: Vec<&str> = columns.iter().map(|c| c.dtype.as_str()).collect();
+    let null_counts: Vec<i64> = columns.iter().map(|c| c.null_count as i64).collect();
+    let samples: Vec<String> = columns.iter().map(|c| c.samples.join(", ")).collect();
+    let samples: Vec<&str> = samples.iter().map(|s| s.as_str()).collect();
+
+    let df = DataFrame::new(vec![
+        Series::new("column_name", names),
+        Series::new("data_type", dtypes),
+        Series::new("null_count", null_counts),
+        Series::new("samples", samples),
+    ])?;
+    Ok(DataSet(df))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query;
+    use crate::testing::temp_source;
+
+    #[tokio::test]
+    async fn describe_should_work() {
+        let source = temp_source("describe.csv", "name,age\ntom,10\njerry,\nspike,5\n");
+
+        let columns = schema(&source).await.unwrap();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0].name, "name");
+        assert_eq!(columns[0].samples, vec!["tom", "jerry", "spike"]);
+        assert_eq!(columns[1].null_count, 1);
+
+        let ds = query(format!("DESCRIBE {}", source)).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        assert_eq!(ds.width(), 4);
+    }
+}
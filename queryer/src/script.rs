@@ -0,0 +1,152 @@
+use crate::Param;
+use anyhow::{anyhow, Result};
+
+/// 脚本中的一条语句
+#[derive(Debug, PartialEq)]
+pub(crate) enum Statement<'a> {
+    /// SET name = value 或者 SET name TO value，之后的语句可以用 `${name}` 引用
+    Set(&'a str, Param),
+    /// 其它语句，交给 Session::query 执行
+    Query(&'a str),
+}
+
+/// 按分号拆分脚本，字符串、带引号的标识符和注释里的分号不算。
+/// 去掉每条语句开头的注释，空语句会被忽略
+pub(crate) fn split(script: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut chars = script.char_indices().peekable();
+    let mut start = 0;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                // 引号内重复两次表示转义，正好相当于结束之后马上又开始
+                for (_, v) in chars.by_ref() {
+                    if v == c {
+                        break;
+                    }
+                }
+            }
+            '-' if matches!(chars.peek(), Some((_, '-'))) => {
+                for (_, v) in chars.by_ref() {
+                    if v == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+                let mut star = false;
+                for (_, v) in chars.by_ref() {
+                    if star && v == '/' {
+                        break;
+                    }
+                    star = v == '*';
+                }
+            }
+            ';' => {
+                statements.push(&script[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push(&script[start..]);
+    statements
+        .into_iter()
+        .map(strip_comments)
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// 识别 SET 语句，其它的语句原样返回
+pub(crate) fn parse(sql: &str) -> Result<Statement<'_>> {
+    let rest = match sql.split_once(char::is_whitespace) {
+        Some((word, rest)) if word.eq_ignore_ascii_case("SET") => rest.trim(),
+        _ => return Ok(Statement::Query(sql)),
+    };
+    let invalid = || anyhow!("Invalid SET statement: {}", sql);
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .ok_or_else(invalid)?;
+    let (name, value) = rest.split_at(end);
+    let value = value.trim_start();
+    let value = match value.strip_prefix('=') {
+        Some(v) => v,
+        None => match value.split_once(char::is_whitespace) {
+            Some((to, v)) if to.eq_ignore_ascii_case("TO") => v,
+            _ => return Err(invalid()),
+        },
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(anyhow!("Invalid variable name in: {}", sql));
+    }
+    Ok(Statement::Set(name, parse_value(value.trim())?))
+}
+
+/// 变量的值只能是字面量：数字、'字符串'、true / false 或者 NULL
+fn parse_value(value: &str) -> Result<Param> {
+    if let Some(v) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return Ok(Param::Text(v.replace("''", "'")));
+    }
+    match value.to_lowercase().as_str() {
+        "null" => Ok(Param::Null),
+        "true" => Ok(Param::Bool(true)),
+        "false" => Ok(Param::Bool(false)),
+        v => v
+            .parse()
+            .map(Param::Number)
+            .map_err(|_| anyhow!("Unsupported value for SET: {}", value)),
+    }
+}
+
+/// 去掉语句开头的注释行和 `/* */` 注释
+fn strip_comments(sql: &str) -> &str {
+    let mut sql = sql.trim();
+    loop {
+        if sql.starts_with("--") {
+            sql = sql.split_once('\n').map_or("", |(_, rest)| rest.trim());
+        } else if sql.starts_with("/*") {
+            sql = sql[2..]
+                .split_once("*/")
+                .map_or("", |(_, rest)| rest.trim());
+        } else {
+            return sql;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_should_work() {
+        let script = "SET name = 'a;b';\n-- comment; not a statement\nSELECT \"x;y\" FROM t;;\n  SELECT 1 -- done;";
+        assert_eq!(
+            split(script),
+            [
+                "SET name = 'a;b'",
+                "SELECT \"x;y\" FROM t",
+                "SELECT 1 -- done;",
+            ]
+        );
+
+        let script = "/* setup; */ SET a = 1;\nSELECT /* a; b */ a FROM t; /* trailing; */";
+        assert_eq!(split(script), ["SET a = 1", "SELECT /* a; b */ a FROM t"]);
+    }
+
+    #[test]
+    fn parse_should_work() {
+        assert_eq!(
+            parse("set deaths = 500").unwrap(),
+            Statement::Set("deaths", Param::Number(500.0))
+        );
+        assert_eq!(
+            parse("SET name TO 'Cote d''Ivoire'").unwrap(),
+            Statement::Set("name", Param::Text("Cote d'Ivoire".to_owned()))
+        );
+        assert_eq!(parse("SELECT 1").unwrap(), Statement::Query("SELECT 1"));
+        assert!(parse("SET deaths").is_err());
+        assert!(parse("SET a = b").is_err());
+    }
+}
@@ -0,0 +1,441 @@
+use crate::command::Command;
+use crate::database;
+use crate::fetcher::retrieve_data;
+use crate::loader::detect_content;
+use crate::materialize::Materialized;
+use crate::memory::{self, MemoryTables};
+use crate::pushdown::Pushdown;
+use crate::script::{self, Statement};
+use crate::{
+    bind_params, explain, schema, select, Catalog, ColumnInfo, DataSet, LimitError, Param,
+    QueryOptions,
+};
+use anyhow::{anyhow, Context, Result};
+use polars::prelude::DataFrame;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio::time;
+use tracing::info;
+
+/// 查询会话，保存注册过的表、物化的表、应用传入的 DataFrame 和脚本中设置的变量。
+/// SQL 里可以用表名代替完整的数据源地址
+#[derive(Debug, Default, Clone)]
+pub struct Session {
+    tables: HashMap<String, String>,
+    /// 物化的表刷新需要的状态，数据和应用传入的 DataFrame 一起放在 frames 里
+    materialized: HashMap<String, Materialized>,
+    frames: MemoryTables,
+    variables: HashMap<String, Param>,
+    options: QueryOptions,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 设置查询的资源限制
+    pub fn with_options(mut self, options: QueryOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &QueryOptions {
+        &self.options
+    }
+
+    /// 把数据源注册成表，之后可以 `SELECT * FROM <name>`
+    pub fn register(&mut self, name: impl Into<String>, source: impl Into<String>) {
+        let name = name.into();
+        self.materialized.remove(&name);
+        self.frames.remove(&name);
+        self.tables.insert(name, source.into());
+    }
+
+    /// 把应用中已有的 DataFrame 或者 DataSet 注册成表，查询时直接使用，不会复制数据
+    pub fn register_dataframe(&mut self, name: impl Into<String>, df: impl Into<DataFrame>) {
+        let name = name.into();
+        self.tables.remove(&name);
+        self.materialized.remove(&name);
+        self.frames.insert(name, df.into());
+    }
+
+    /// 取消注册 DataFrame，返回原来的 DataFrame；物化的表要用 deregister 取消
+    pub fn deregister_dataframe(&mut self, name: &str) -> Option<DataFrame> {
+        if self.materialized.contains_key(name) {
+            return None;
+        }
+        self.frames.remove(name)
+    }
+
+    /// 把数据源物化成表：数据读入之后保存在会话中，之后的查询不再访问数据源。
+    /// watermark 是只增不减的列（比如时间或者自增的 id），refresh 时用来只读取新增的行
+    pub async fn materialize(
+        &mut self,
+        name: impl Into<String>,
+        source: &str,
+        watermark: impl Into<String>,
+    ) -> Result<()> {
+        let source = self.resolve(source)?.to_owned();
+        let (table, data) = self
+            .with_timeout(Materialized::load(source, watermark.into(), &self.options))
+            .await?;
+        let name = name.into();
+        self.tables.remove(&name);
+        self.frames.insert(name.clone(), data);
+        self.materialized.insert(name, table);
+        Ok(())
+    }
+
+    /// 把数据源新增的行追加到物化的表中，返回新增的行数。
+    /// 追加写入的 csv 文件从上次读到的位置继续读，数据库只查询 watermark 更大的行，
+    /// 其它数据源重新读取之后只保留 watermark 更大的行
+    pub async fn refresh(&mut self, name: &str) -> Result<usize> {
+        let (mut table, mut data) = match (self.materialized.get(name), self.frames.get(name)) {
+            (Some(table), Some(data)) => (table.clone(), data.clone()),
+            _ => return Err(anyhow!("Materialized table {} not found", name)),
+        };
+        let rows = self
+            .with_timeout(table.refresh(&mut data, &self.options))
+            .await?;
+        self.frames.insert(name.to_owned(), data);
+        self.materialized.insert(name.to_owned(), table);
+        Ok(rows)
+    }
+
+    /// 取消注册，返回原来的数据源地址
+    pub fn deregister(&mut self, name: &str) -> Option<String> {
+        self.tables.remove(name).or_else(|| {
+            let table = self.materialized.remove(name)?;
+            self.frames.remove(name);
+            Some(table.source)
+        })
+    }
+
+    /// 所有注册过的表，包括物化的表：(表名, 数据源地址)
+    pub fn tables(&self) -> impl Iterator<Item = (&str, &str)> {
+        let materialized = self.materialized.iter().map(|(k, v)| (k, &v.source));
+        self.tables
+            .iter()
+            .chain(materialized)
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// 设置变量，run_script 执行的语句中的 `${name}` 会被替换成它的值
+    pub fn set_variable(&mut self, name: impl Into<String>, value: Param) {
+        self.variables.insert(name.into(), value);
+    }
+
+    /// 所有设置过的变量
+    pub fn variables(&self) -> &HashMap<String, Param> {
+        &self.variables
+    }
+
+    /// 补全用的上下文，包含所有注册过的表名；列名由调用者根据已知的 schema 补充
+    pub fn catalog(&self) -> Catalog {
+        // 物化的表的数据也在 frames 里
+        let mut tables: Vec<String> = self
+            .tables
+            .keys()
+            .map(|k| k.as_str())
+            .chain(self.frames.names())
+            .map(|k| k.to_owned())
+            .collect();
+        tables.sort();
+        Catalog {
+            tables,
+            columns: Vec::new(),
+        }
+    }
+
+    /// 在当前会话中执行一条 SQL 语句，超时或者结果行数超过限制时返回 LimitError。
+    /// 超时包括获取数据和 polars 的计算
+    pub async fn query<T: AsRef<str>>(&self, sql: T) -> Result<DataSet> {
+        let ds = self.with_timeout(self.execute(sql.as_ref())).await?;
+        self.options.check_rows(ds.height())?;
+        Ok(ds)
+    }
+
+    /// 依次执行用分号分隔的多条语句，返回每条查询语句的结果。
+    /// `SET name = value` 设置变量，之后的语句中的 `${name}` 会被替换成它的值。
+    /// 某条语句出错时停止执行，错误信息中包含出错的语句
+    pub async fn run_script<T: AsRef<str>>(&mut self, script: T) -> Result<Vec<DataSet>> {
+        let mut results = Vec::new();
+        for (i, sql) in script::split(script.as_ref()).into_iter().enumerate() {
+            let context = || format!("Failed to run statement {}: {}", i + 1, sql);
+            let sql = bind_params(sql, &self.variables).with_context(context)?;
+            match script::parse(&sql).with_context(context)? {
+                Statement::Set(name, value) => self.set_variable(name, value),
+                Statement::Query(sql) => results.push(self.query(sql).await.with_context(context)?),
+            }
+        }
+        Ok(results)
+    }
+
+    /// 获取表或者数据源的 schema，同样受 options 的限制
+    pub async fn schema(&self, source: &str) -> Result<Vec<ColumnInfo>> {
+        self.with_timeout(async { Ok(self.load(source).await?.columns_info()) })
+            .await
+    }
+
+    async fn with_timeout<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.options.timeout {
+            Some(t) => time::timeout(t, fut)
+                .await
+                .map_err(|_| LimitError::Timeout(t))?,
+            None => fut.await,
+        }
+    }
+
+    async fn execute(&self, sql: &str) -> Result<DataSet> {
+        match Command::parse(sql)? {
+            Command::Describe(source) => {
+                schema::to_dataset(&self.load(&source).await?.columns_info())
+            }
+            Command::Summarize(statement) => {
+                let ds = select(&statement, self).await?;
+                tokio::task::spawn_blocking(move || ds.summary()).await?
+            }
+            Command::Select(statement) => select(&statement, self).await,
+            Command::Explain(statement) => explain(&statement, self),
+        }
+    }
+
+    /// 读入表名或者数据源地址对应的数据
+    pub async fn load(&self, source: &str) -> Result<DataSet> {
+        self.scan(source, &Pushdown::default()).await
+    }
+
+    /// 读入数据，数据源支持的话会下推查询条件
+    pub(crate) async fn scan(&self, source: &str, pushdown: &Pushdown) -> Result<DataSet> {
+        if let Some(ds) = self.frames.load(source, pushdown.columns.as_deref())? {
+            return Ok(ds);
+        }
+        let source = self.resolve(source)?;
+        info!("retrieving data from source: {}", source);
+        if database::is_database(source) {
+            self.options.check_source(source)?;
+            let ds = database::load(source, pushdown).await?;
+            self.options.check_frame(&ds)?;
+            return Ok(ds);
+        }
+        if memory::is_memory(source) {
+            self.options.check_source(source)?;
+            let ds = memory::load(source, pushdown.columns.as_deref())?;
+            self.options.check_frame(&ds)?;
+            return Ok(ds);
+        }
+        // detect_content，怎么 detect 不用要，重要的是它能根据内容返回 DataSet
+        detect_content(source, retrieve_data(source, &self.options).await?)?
+            .load_columns(pushdown.columns.as_deref())
+    }
+
+    /// 表名换成注册的数据源地址；物化的表和 DataFrame 数据已经在内存中，保留表名；
+    /// 不是表名的话，必须是一个 url
+    pub(crate) fn resolve<'a>(&'a self, source: &'a str) -> Result<&'a str> {
+        match self.tables.get(source) {
+            Some(v) => Ok(v),
+            None if self.frames.contains(source) => Ok(source),
+            None if source.contains("://") => Ok(source),
+            None => Err(anyhow!("Table {} not found", source)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_source;
+    use crate::ErrorKind;
+    use polars::prelude::{NamedFrom, Series, TakeRandom};
+
+    #[tokio::test]
+    async fn registered_table_should_work() {
+        let mut session = Session::new();
+        session.register(
+            "pets",
+            temp_source("session.csv", "name,age\ntom,10\njerry,3\n"),
+        );
+        let ds = session
+            .query("SELECT name FROM pets WHERE age > 5")
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 1);
+
+        assert!(session.query("SELECT name FROM cats").await.is_err());
+
+        let session = session.with_options(QueryOptions {
+            max_rows: Some(1),
+            ..Default::default()
+        });
+        let err = session.query("SELECT name FROM pets").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitError>(),
+            Some(&LimitError::TooManyRows { rows: 2, limit: 1 })
+        );
+        assert_eq!(ErrorKind::of(&err), ErrorKind::Limit);
+    }
+
+    #[tokio::test]
+    async fn source_limit_should_cover_memory_tables() {
+        let df = DataFrame::new(vec![Series::new("name", &["tom", "jerry"])]).unwrap();
+        crate::register_memory_table("session_limit_pets", df);
+        let session = Session::new().with_options(QueryOptions {
+            max_source_bytes: Some(4),
+            ..Default::default()
+        });
+        let err = session
+            .query("SELECT name FROM mem://session_limit_pets")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitError>(),
+            Some(&LimitError::SourceTooLarge { limit: 4 })
+        );
+
+        let session = session.with_options(QueryOptions {
+            max_source_bytes: Some(8),
+            ..Default::default()
+        });
+        let ds = session
+            .query("SELECT name FROM mem://session_limit_pets")
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 2);
+    }
+
+    #[tokio::test]
+    async fn timeout_should_cover_compute() {
+        crate::register_function("slow_identity", |args| {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            Ok(args[0].clone())
+        });
+        let source = crate::testing::temp_source("slow.csv", "age\n10\n3\n");
+        let mut session = Session::new().with_options(QueryOptions {
+            timeout: Some(std::time::Duration::from_millis(50)),
+            ..Default::default()
+        });
+        session.register("pets", source);
+        let err = session
+            .query("SELECT slow_identity(age) FROM pets")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitError>(),
+            Some(LimitError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn registered_dataframe_should_work() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["tom", "jerry", "spike"]),
+            Series::new("age", &[10i64, 3, 8]),
+        ])
+        .unwrap();
+
+        let mut session = Session::new();
+        session.register_dataframe("pets", df);
+        let ds = session
+            .query("SELECT name, age FROM pets WHERE age > 5 ORDER BY age")
+            .await
+            .unwrap();
+        assert_eq!(ds.height(), 2);
+
+        // 查询的结果可以继续用 SQL 查询
+        let ds = ds
+            .sql("SELECT name FROM self WHERE age < 10")
+            .await
+            .unwrap();
+        assert_eq!(
+            ds.column("name").unwrap().utf8().unwrap().get(0),
+            Some("spike")
+        );
+
+        assert!(session.deregister_dataframe("pets").is_some());
+        assert!(session.query("SELECT name FROM pets").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nulls_first_and_last_should_work() {
+        let df = DataFrame::new(vec![
+            Series::new("id", &[0i64, 1, 2, 3]),
+            Series::new("n", &[Some(3i64), None, Some(1), None]),
+        ])
+        .unwrap();
+        let mut session = Session::new();
+        session.register_dataframe("t", df);
+        let ids = |ds: DataSet| -> Vec<Option<i64>> {
+            ds.column("id")
+                .unwrap()
+                .i64()
+                .unwrap()
+                .into_iter()
+                .collect()
+        };
+        let cases = [
+            ("n NULLS LAST, id", [2, 0, 1, 3]),
+            ("n DESC NULLS FIRST, id", [1, 3, 0, 2]),
+            ("n, id", [1, 3, 2, 0]),
+            ("n DESC, id", [0, 2, 1, 3]),
+        ];
+        for (order, expected) in cases {
+            let sql = format!("SELECT id FROM t ORDER BY {}", order);
+            let ds = session.query(&sql).await.unwrap();
+            let expected: Vec<_> = expected.iter().map(|v| Some(*v)).collect();
+            assert_eq!(ids(ds), expected, "{}", sql);
+        }
+    }
+
+    #[tokio::test]
+    async fn integer_arithmetic_should_work() {
+        let df = DataFrame::new(vec![Series::new("a", &[5i64, -3])]).unwrap();
+        let mut session = Session::new();
+        session.register_dataframe("t", df);
+        let ds = session
+            .query("SELECT a / 2 AS x, a % 2 AS y, a / 2.0 AS z, a / 0 AS w FROM t")
+            .await
+            .unwrap();
+        let values = |name: &str| -> Vec<Option<f64>> {
+            let s = ds.column(name).unwrap();
+            let s = s
+                .cast_with_dtype(&polars::prelude::DataType::Float64)
+                .unwrap();
+            s.f64().unwrap().into_iter().collect()
+        };
+        assert_eq!(values("x"), vec![Some(2.0), Some(-1.0)]);
+        assert_eq!(values("y"), vec![Some(1.0), Some(-1.0)]);
+        assert_eq!(values("z"), vec![Some(2.5), Some(-1.5)]);
+        assert_eq!(ds.column("w").unwrap().null_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn run_script_should_work() {
+        let source = temp_source("script.csv", "name,age\ntom,10\njerry,3\nspike,8\n");
+        let mut session = Session::new();
+        session.register("pets", source);
+        let script = "
+            -- 年龄超过 ${min_age} 的宠物
+            SET min_age = 5;
+            SET name = 'jerry';
+            SELECT name FROM pets WHERE age > ${min_age} ORDER BY age;
+            DESCRIBE pets;
+            SELECT age FROM pets WHERE name = ${name};
+        ";
+        let results = session.run_script(script).await.unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].height(), 2);
+        assert_eq!(results[1].height(), 2);
+        assert_eq!(
+            results[2].column("age").unwrap().i64().unwrap().get(0),
+            Some(3)
+        );
+        assert_eq!(session.variables()["min_age"], Param::Number(5.0));
+
+        let err = session
+            .run_script("SELECT name FROM pets; SELECT ${missing} FROM pets")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("statement 2"));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::DataSet;
+use anyhow::Result;
+use polars::prelude::*;
+
+/// 只对数值列计算的统计项
+const NUMERIC_STATS: [&str; 7] = ["min", "max", "mean", "std", "q25", "q50", "q75"];
+
+impl DataSet {
+    /// 计算每一列的统计信息，类似 pandas 的 describe()。结果每行对应原来的一列：
+    /// count, null_count, distinct_count, 以及数值列的 min, max, mean, std 和分位数
+    pub fn summary(&self) -> Result<DataSet> {
+        let columns = self.get_columns();
+
+        // 所有统计项放在一个 lazy select 里，由 polars 一次算完
+        let exprs: Vec<Expr> = columns
+            .iter()
+            .flat_map(|s| aggregations(s.name(), is_numeric(s.dtype())))
+            .collect();
+        let stats = self.0.clone().lazy().select(exprs).collect()?;
+        let get = |name: &str, stat: &str| -> Result<Option<f64>> {
+            match stats.column(&stat_name(name, stat)) {
+                Ok(s) => Ok(s.f64()?.get(0)),
+                // 非数值列没有这一项
+                Err(_) => Ok(None),
+            }
+        };
+
+        let height = self.height() as i64;
+        let mut names = Vec::with_capacity(columns.len());
+        let mut dtypes = Vec::with_capacity(columns.len());
+        let mut counts = Vec::with_capacity(columns.len());
+        let mut null_counts = Vec::with_capacity(columns.len());
+        let mut distinct_counts = Vec::with_capacity(columns.len());
+        for s in columns {
+            let nulls = get(s.name(), "null_count")?.unwrap_or(0.0) as i64;
+            names.push(s.name().to_owned());
+            dtypes.push(s.dtype().to_string());
+            counts.push(height - nulls);
+            null_counts.push(nulls);
+            distinct_counts.push(get(s.name(), "distinct_count")?.map(|v| v as i64));
+        }
+        let names: Vec<&str> = names.iter().map(|v| v.as_str()).collect();
+        let dtypes: Vec<&str> = dtypes.iter().map(|v| v.as_str()).collect();
+
+        let mut result = vec![
+            Series::new("column_name", names),
+            Series::new("data_type", dtypes),
+            Series::new("count", counts),
+            Series::new("null_count", null_counts),
+            Series::new("distinct_count", distinct_counts),
+        ];
+        for stat in NUMERIC_STATS {
+            let values = columns
+                .iter()
+                .map(|s| get(s.name(), stat))
+                .collect::<Result<Vec<_>>>()?;
+            result.push(Series::new(stat, values));
+        }
+
+        Ok(DataSet(DataFrame::new(result)?))
+    }
+}
+
+/// 一列需要计算的所有统计项，结果统一转换成 f64，方便后面读取
+fn aggregations(name: &str, numeric: bool) -> Vec<Expr> {
+    let mut exprs = vec![
+        (
+            col(name).is_null().cast(DataType::UInt32).sum(),
+            "null_count",
+        ),
+        (col(name).n_unique(), "distinct_count"),
+    ];
+    if numeric {
+        // polars 0.15 的 mean 把空值也算进了分母，先去掉空值
+        let v = || {
+            col(name)
+                .filter(col(name).is_not_null())
+                .cast(DataType::Float64)
+        };
+        exprs.extend([
+            (v().min(), "min"),
+            (v().max(), "max"),
+            (v().mean(), "mean"),
+            (v().std(), "std"),
+            (v().quantile(0.25), "q25"),
+            (v().quantile(0.5), "q50"),
+            (v().quantile(0.75), "q75"),
+        ]);
+    }
+
+    exprs
+        .into_iter()
+        .map(|(expr, stat)| expr.cast(DataType::Float64).alias(&stat_name(name, stat)))
+        .collect()
+}
+
+/// 整数和浮点数是数值类型，日期不算
+pub(crate) fn is_numeric(dtype: &DataType) -> bool {
+    matches!(
+        dtype,
+        DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::Float32
+            | DataType::Float64
+    )
+}
+
+fn stat_name(name: &str, stat: &str) -> String {
+    format!("{}:{}", name, stat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_should_work() {
+        let df = DataFrame::new(vec![
+            Series::new("name", &["tom", "jerry", "tom", "spike"]),
+            Series::new("age", &[Some(1i64), Some(2), Some(3), None]),
+        ])
+        .unwrap();
+
+        let summary = DataSet(df).summary().unwrap();
+        assert_eq!(summary.height(), 2);
+
+        let distinct = summary.column("distinct_count").unwrap().i64().unwrap();
+        assert_eq!(distinct.get(0), Some(3));
+        let counts = summary.column("count").unwrap().i64().unwrap();
+        assert_eq!(counts.get(1), Some(3));
+        let mean = summary.column("mean").unwrap().f64().unwrap();
+        assert_eq!(mean.get(0), None);
+        assert_eq!(mean.get(1), Some(2.0));
+    }
+}
@@ -0,0 +1,18 @@
+//! 测试用的辅助函数。并行执行的测试各自使用不同的文件，不会互相覆盖
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// 临时目录下一个不重复的文件路径，name 作为文件名的结尾，保留扩展名
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("queryer_{}_{}_{}", std::process::id(), n, name))
+}
+
+/// 把 content 写到一个不重复的临时文件里，返回 file:// 地址
+pub fn temp_source(name: &str, content: &str) -> String {
+    let path = temp_path(name);
+    std::fs::write(&path, content).unwrap();
+    format!("file://{}", path.display())
+}
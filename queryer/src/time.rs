@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+use std::str::FromStr;
+
+/// 支持的日期时间格式，按顺序尝试。秒后面可以有小数，比如 postgres 的 timestamp
+const DATETIME_FORMATS: [&str; 3] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S",
+];
+/// 支持的日期格式，按顺序尝试
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%Y/%m/%d", "%Y%m%d"];
+
+/// 时间分桶的粒度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBucket {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl FromStr for TimeBucket {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(TimeBucket::Day),
+            "week" => Ok(TimeBucket::Week),
+            "month" => Ok(TimeBucket::Month),
+            "quarter" => Ok(TimeBucket::Quarter),
+            "year" => Ok(TimeBucket::Year),
+            v => Err(anyhow!("Time bucket {} not supported", v)),
+        }
+    }
+}
+
+impl TimeBucket {
+    /// 把时间截断到所在分桶的起点，一周从周一开始
+    pub fn truncate(&self, dt: NaiveDateTime) -> NaiveDateTime {
+        let date = dt.date();
+        let start = match self {
+            TimeBucket::Day => Some(date),
+            TimeBucket::Week => {
+                Some(date - Duration::days(date.weekday().num_days_from_monday() as i64))
+            }
+            TimeBucket::Month => NaiveDate::from_ymd_opt(date.year(), date.month(), 1),
+            TimeBucket::Quarter => {
+                NaiveDate::from_ymd_opt(date.year(), (date.month() - 1) / 3 * 3 + 1, 1)
+            }
+            TimeBucket::Year => NaiveDate::from_ymd_opt(date.year(), 1, 1),
+        };
+        start.and_then(|d| d.and_hms_opt(0, 0, 0)).unwrap_or(dt)
+    }
+}
+
+/// 解析常见格式的日期字符串，不包含时间
+pub fn parse_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    DATE_FORMATS
+        .iter()
+        .find_map(|fmt| NaiveDate::parse_from_str(s, fmt).ok())
+}
+
+/// 解析常见格式的日期或者日期时间字符串
+pub fn parse_datetime(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    DATETIME_FORMATS
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(s, fmt).ok())
+        .or_else(|| {
+            DATE_FORMATS
+                .iter()
+                .find_map(|fmt| NaiveDate::parse_from_str(s, fmt).ok())
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// 解析 `7 days`、`1 week`、`12 hours` 这样的时间间隔
+pub(crate) fn parse_interval(s: &str) -> Result<Duration> {
+    let (n, unit) = s
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| anyhow!("Invalid interval {}, expect something like '7 days'", s))?;
+    let n: i64 = n.parse()?;
+    let interval = match unit.trim().to_lowercase().trim_end_matches('s') {
+        "second" => Duration::seconds(n),
+        "minute" => Duration::minutes(n),
+        "hour" => Duration::hours(n),
+        "day" => Duration::days(n),
+        "week" => Duration::weeks(n),
+        v => return Err(anyhow!("Interval unit {} not supported", v)),
+    };
+    if interval <= Duration::zero() {
+        return Err(anyhow!("Interval should be positive, got {}", s));
+    }
+    Ok(interval)
+}
+
+/// 按固定间隔分桶，和 TimescaleDB 一样以 2000-01-03（周一）为起点对齐
+pub(crate) fn time_bucket(interval: Duration, dt: NaiveDateTime) -> NaiveDateTime {
+    let origin = NaiveDate::from_ymd_opt(2000, 1, 3)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .unwrap();
+    let step = interval.num_seconds();
+    let n = (dt - origin).num_seconds().div_euclid(step);
+    origin + Duration::seconds(n * step)
+}
+
+/// 把 Date32 / Date64 / 字符串列转换成日期时间，无法解析的是 None
+pub(crate) fn to_datetimes(s: &Series) -> Result<Vec<Option<NaiveDateTime>>, PolarsError> {
+    let values = match s.dtype() {
+        DataType::Date32 => s
+            .cast_with_dtype(&DataType::Int32)?
+            .i32()?
+            .into_iter()
+            .map(|v| v.map(|v| epoch() + Duration::days(v as i64)))
+            .collect(),
+        DataType::Date64 => s
+            .cast_with_dtype(&DataType::Int64)?
+            .i64()?
+            .into_iter()
+            .map(|v| v.and_then(NaiveDateTime::from_timestamp_millis))
+            .collect(),
+        _ => s
+            .cast_with_dtype(&DataType::Utf8)?
+            .utf8()?
+            .into_iter()
+            .map(|v| v.and_then(parse_datetime))
+            .collect(),
+    };
+    Ok(values)
+}
+
+/// 生成 polars 的 Date32 列，值是距离 1970-01-01 的天数
+pub(crate) fn date_series(
+    name: &str,
+    values: Vec<Option<NaiveDate>>,
+) -> Result<Series, PolarsError> {
+    let days: Vec<Option<i32>> = values
+        .into_iter()
+        .map(|v| v.map(|d| (d - epoch().date()).num_days() as i32))
+        .collect();
+    Series::new(name, days).cast_with_dtype(&DataType::Date32)
+}
+
+/// 生成 polars 的 Date64 列，精度是毫秒
+pub(crate) fn datetime_series(
+    name: &str,
+    values: Vec<Option<NaiveDateTime>>,
+) -> Result<Series, PolarsError> {
+    let millis: Vec<Option<i64>> = values
+        .into_iter()
+        .map(|v| v.map(|dt| dt.timestamp_millis()))
+        .collect();
+    Series::new(name, millis).cast_with_dtype(&DataType::Date64)
+}
+
+/// 把 Date32 / Date64 列格式化成字符串，其它类型直接转换。polars 不支持把日期 cast 成字符串
+pub(crate) fn to_strings(s: Series) -> Result<Series, PolarsError> {
+    let fmt = match s.dtype() {
+        DataType::Date32 => "%Y-%m-%d",
+        DataType::Date64 => "%Y-%m-%d %H:%M:%S",
+        _ => return s.cast_with_dtype(&DataType::Utf8),
+    };
+    let values: Vec<Option<String>> = to_datetimes(&s)?
+        .into_iter()
+        .map(|v| v.map(|dt| dt.format(fmt).to_string()))
+        .collect();
+    Ok(Series::new(s.name(), values))
+}
+
+/// 字符串列里的值如果都是日期或者日期时间，就转换成 Date32 / Date64 列
+pub(crate) fn parse_dates(df: DataFrame) -> Result<DataFrame> {
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(|s| {
+            if s.dtype() != &DataType::Utf8 {
+                return Ok(s.clone());
+            }
+            let values: Vec<Option<&str>> = s.utf8()?.into_iter().collect();
+            let mut non_null = values.iter().flatten().peekable();
+            if non_null.peek().is_none() {
+                return Ok(s.clone());
+            }
+            if non_null.clone().all(|v| parse_date(v).is_some()) {
+                let dates = values.iter().map(|v| v.and_then(parse_date)).collect();
+                return date_series(s.name(), dates);
+            }
+            if non_null.all(|v| parse_datetime(v).is_some()) {
+                let datetimes = values.iter().map(|v| v.and_then(parse_datetime)).collect();
+                return datetime_series(s.name(), datetimes);
+            }
+            Ok(s.clone())
+        })
+        .collect::<Result<Vec<_>, PolarsError>>()?;
+    Ok(DataFrame::new(columns)?)
+}
+
+fn epoch() -> NaiveDateTime {
+    NaiveDateTime::from_timestamp_millis(0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_should_work() {
+        let dt = parse_datetime("2021-08-19 10:20:30").unwrap();
+        let truncate = |bucket: &str| {
+            let bucket: TimeBucket = bucket.parse().unwrap();
+            bucket.truncate(dt).format("%Y-%m-%d %H:%M:%S").to_string()
+        };
+        assert_eq!(truncate("day"), "2021-08-19 00:00:00");
+        assert_eq!(truncate("week"), "2021-08-16 00:00:00");
+        assert_eq!(truncate("month"), "2021-08-01 00:00:00");
+        assert_eq!(truncate("quarter"), "2021-07-01 00:00:00");
+        assert_eq!(truncate("year"), "2021-01-01 00:00:00");
+    }
+
+    #[test]
+    fn time_bucket_should_work() {
+        let interval = parse_interval("7 days").unwrap();
+        let dt = parse_datetime("2021-08-19 10:20:30").unwrap();
+        // 2021-08-16 是周一，和 2000-01-03 相差整数周
+        assert_eq!(
+            time_bucket(interval, dt),
+            parse_datetime("2021-08-16").unwrap()
+        );
+        let dt = parse_datetime("1999-12-31").unwrap();
+        assert_eq!(
+            time_bucket(interval, dt),
+            parse_datetime("1999-12-27").unwrap()
+        );
+        assert!(parse_interval("0 days").is_err());
+        assert!(parse_interval("3 fortnights").is_err());
+    }
+
+    #[test]
+    fn parse_dates_should_work() {
+        let df = DataFrame::new(vec![
+            Series::new("date", &[Some("2021-08-01"), None, Some("2021-08-03")]),
+            Series::new(
+                "time",
+                &["2021-08-01 10:00:00.25", "2021-08-01", "2021-08-03"],
+            ),
+            Series::new("name", &["a", "2021-08-01", "c"]),
+        ])
+        .unwrap();
+        let df = parse_dates(df).unwrap();
+        assert_eq!(df.column("date").unwrap().dtype(), &DataType::Date32);
+        assert_eq!(df.column("time").unwrap().dtype(), &DataType::Date64);
+        assert_eq!(df.column("name").unwrap().dtype(), &DataType::Utf8);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::convert::Expression;
+use crate::functions;
+use crate::loader::{to_cells, to_series, Cell};
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use polars::prelude::*;
+use sqlparser::ast::{Expr as SqlExpr, Function, FunctionArg};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// 自定义的标量函数：每个参数是一列，返回同样长度的一列
+pub type ScalarFunction = dyn Fn(&[Series]) -> Result<Series> + Send + Sync;
+
+/// 注册过的函数，所有查询共享。函数名不区分大小写
+static FUNCTIONS: Lazy<RwLock<HashMap<String, Arc<ScalarFunction>>>> = Lazy::new(Default::default);
+
+/// 注册一个可以在 SQL 中调用的函数，同名的函数会被替换。内置的函数优先，不能被覆盖
+pub fn register_function<F>(name: &str, f: F)
+where
+    F: Fn(&[Series]) -> Result<Series> + Send + Sync + 'static,
+{
+    FUNCTIONS
+        .write()
+        .unwrap()
+        .insert(name.to_lowercase(), Arc::new(f));
+}
+
+/// 注册一个逐行计算的函数。f 一次收到所有的行，每一行是参数的值，返回每一行的结果；
+/// 结果的类型根据返回的值推断。Python / JS 的绑定用它在一次调用中处理所有的行
+pub fn register_row_function<F>(name: &str, f: F)
+where
+    F: Fn(&[Vec<Cell>]) -> Result<Vec<Cell>> + Send + Sync + 'static,
+{
+    register_function(name, move |args| {
+        let columns = args.iter().map(to_cells).collect::<Result<Vec<_>>>()?;
+        let len = args.first().map_or(0, |s| s.len());
+        let rows: Vec<Vec<Cell>> = (0..len)
+            .map(|i| columns.iter().map(|c| c[i].clone()).collect())
+            .collect();
+        let values = f(&rows)?;
+        if values.len() != len {
+            return Err(anyhow!(
+                "Function returned {} values for {} rows",
+                values.len(),
+                len
+            ));
+        }
+        Ok(to_series("", &values.iter().collect::<Vec<_>>()))
+    });
+}
+
+/// 取消注册，返回之前是否注册过
+pub fn deregister_function(name: &str) -> bool {
+    FUNCTIONS
+        .write()
+        .unwrap()
+        .remove(&name.to_lowercase())
+        .is_some()
+}
+
+/// 是否是注册过的自定义函数
+pub(crate) fn is_registered(name: &str) -> bool {
+    !functions::is_builtin(name) && FUNCTIONS.read().unwrap().contains_key(&name.to_lowercase())
+}
+
+/// 收集表达式中调用的自定义函数，参数里的调用排在前面。
+/// 每种表达式都要列出来，sqlparser 增加了新的表达式时编译器会提醒这里
+pub(crate) fn collect_calls<'a>(expr: &'a SqlExpr, out: &mut Vec<&'a Function>) {
+    let collect_all = |exprs: &'a [SqlExpr], out: &mut Vec<&'a Function>| {
+        exprs.iter().for_each(|expr| collect_calls(expr, out))
+    };
+    match expr {
+        SqlExpr::BinaryOp { left, right, .. } => {
+            collect_calls(left, out);
+            collect_calls(right, out);
+        }
+        SqlExpr::IsNull(expr)
+        | SqlExpr::IsNotNull(expr)
+        | SqlExpr::Nested(expr)
+        | SqlExpr::UnaryOp { expr, .. }
+        | SqlExpr::Cast { expr, .. }
+        | SqlExpr::TryCast { expr, .. }
+        | SqlExpr::Extract { expr, .. }
+        | SqlExpr::Collate { expr, .. }
+        | SqlExpr::InSubquery { expr, .. }
+        | SqlExpr::MapAccess { column: expr, .. } => collect_calls(expr, out),
+        SqlExpr::InList { expr, list, .. } => {
+            collect_calls(expr, out);
+            collect_all(list, out);
+        }
+        SqlExpr::Between {
+            expr, low, high, ..
+        } => {
+            collect_calls(expr, out);
+            collect_calls(low, out);
+            collect_calls(high, out);
+        }
+        SqlExpr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+        } => {
+            collect_calls(expr, out);
+            for expr in substring_from.iter().chain(substring_for) {
+                collect_calls(expr, out);
+            }
+        }
+        SqlExpr::Trim { expr, trim_where } => {
+            collect_calls(expr, out);
+            if let Some((_, expr)) = trim_where {
+                collect_calls(expr, out);
+            }
+        }
+        SqlExpr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            if let Some(expr) = operand {
+                collect_calls(expr, out);
+            }
+            collect_all(conditions, out);
+            collect_all(results, out);
+            if let Some(expr) = else_result {
+                collect_calls(expr, out);
+            }
+        }
+        SqlExpr::ListAgg(agg) => {
+            collect_calls(&agg.expr, out);
+            if let Some(expr) = &agg.separator {
+                collect_calls(expr, out);
+            }
+        }
+        SqlExpr::Function(f) => {
+            for arg in &f.args {
+                match arg {
+                    FunctionArg::Unnamed(expr) | FunctionArg::Named { arg: expr, .. } => {
+                        collect_calls(expr, out)
+                    }
+                }
+            }
+            if is_registered(&f.name.to_string()) {
+                out.push(f);
+            }
+        }
+        // 子查询是另外一个查询，不在这里计算
+        SqlExpr::Identifier(_)
+        | SqlExpr::Wildcard
+        | SqlExpr::QualifiedWildcard(_)
+        | SqlExpr::CompoundIdentifier(_)
+        | SqlExpr::Value(_)
+        | SqlExpr::TypedString { .. }
+        | SqlExpr::Exists(_)
+        | SqlExpr::Subquery(_) => {}
+    }
+}
+
+/// 在过滤之前对所有的行计算自定义函数，结果以调用的 SQL 作为列名加到 df 中，
+/// 之后的表达式把调用当作这一列来使用。SELECT * 不包含这些列
+pub(crate) fn apply(
+    calls: &[&Function],
+    df: DataFrame,
+    selection: Vec<Expr>,
+) -> Result<(DataFrame, Vec<Expr>)> {
+    if calls.is_empty() {
+        return Ok((df, selection));
+    }
+    let columns: Vec<String> = df
+        .get_column_names()
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+    let selection = selection
+        .into_iter()
+        .flat_map(|expr| match expr {
+            Expr::Wildcard => columns.iter().map(|v| col(v)).collect(),
+            expr => vec![expr],
+        })
+        .collect();
+
+    let mut df = df;
+    for f in calls {
+        let name = f.to_string();
+        if df.column(&name).is_ok() {
+            continue;
+        }
+        let udf = FUNCTIONS
+            .read()
+            .unwrap()
+            .get(&f.name.to_string().to_lowercase())
+            .cloned()
+            .ok_or_else(|| anyhow!("Function {} is not registered", f.name))?;
+        if f.args.is_empty() {
+            return Err(anyhow!("Function {} needs at least one argument", f.name));
+        }
+        let args = f
+            .args
+            .iter()
+            .map(|arg| match arg {
+                FunctionArg::Unnamed(expr) => evaluate(&df, expr),
+                FunctionArg::Named { .. } => {
+                    Err(anyhow!("Named arguments are not supported in {}", f))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut s = udf(&args)?;
+        if s.len() != df.height() {
+            return Err(anyhow!(
+                "Function {} returned {} values for {} rows",
+                f.name,
+                s.len(),
+                df.height()
+            ));
+        }
+        s.rename(&name);
+        df.with_column(s)?;
+    }
+    Ok((df, selection))
+}
+
+/// 计算参数，常量会扩展成和 df 一样长
+fn evaluate(df: &DataFrame, expr: &SqlExpr) -> Result<Series> {
+    let expr: Expr = Expression(Box::new(expr.to_owned())).try_into()?;
+    let name = "__udf_argument";
+    let df = df.clone().lazy().with_column(expr.alias(name)).collect()?;
+    Ok(df.column(name)?.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::TyrDialect;
+    use crate::query;
+    use crate::testing::{temp_path, temp_source};
+    use sqlparser::ast::{SetExpr, Statement};
+    use sqlparser::parser::Parser;
+
+    fn data() -> String {
+        temp_source("udf.csv", "country,amount\nus,10\nCn,20\nus,5\n")
+    }
+
+    #[tokio::test]
+    async fn udf_should_work() {
+        register_function("upper_code", |args| {
+            let values: Vec<Option<String>> = args[0]
+                .utf8()?
+                .into_iter()
+                .map(|v| v.map(|v| v.to_uppercase()))
+                .collect();
+            Ok(Series::new("", values))
+        });
+        register_row_function("with_tax", |rows| {
+            rows.iter()
+                .map(|row| match (&row[0], &row[1]) {
+                    (Cell::Int(amount), Cell::Float(rate)) => {
+                        Ok(Cell::Float(*amount as f64 * (1.0 + rate)))
+                    }
+                    _ => Ok(Cell::Null),
+                })
+                .collect()
+        });
+
+        let sql = format!(
+            "SELECT upper_code(country) AS code, with_tax(amount, 0.5) AS total FROM {} \
+            WHERE upper_code(country) = 'US'",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        let codes: Vec<_> = ds
+            .column("code")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(codes, [Some("US"), Some("US")]);
+        let totals: Vec<_> = ds
+            .column("total")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(totals, [Some(15.0), Some(7.5)]);
+
+        // SELECT * 不包含自定义函数计算出来的列
+        let sql = format!("SELECT * FROM {} WHERE with_tax(amount, 0.5) > 20", data());
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.width(), 2);
+        assert_eq!(ds.height(), 1);
+
+        assert!(deregister_function("WITH_TAX"));
+        let sql = format!("SELECT with_tax(amount, 0.5) FROM {}", data());
+        assert!(query(sql).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn udf_in_nested_expressions_should_work() {
+        register_function("double_amount", |args| {
+            let values: Int64Chunked = args[0]
+                .i64()?
+                .into_iter()
+                .map(|v| v.map(|v| v * 2))
+                .collect();
+            Ok(values.into_series())
+        });
+        register_function("is_big", |args| {
+            let values: BooleanChunked = args[0]
+                .i64()?
+                .into_iter()
+                .map(|v| v.map(|v| v > 8))
+                .collect();
+            Ok(values.into_series())
+        });
+
+        let sql = format!("SELECT amount FROM {} WHERE NOT is_big(amount)", data());
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.column("amount").unwrap().i64().unwrap().get(0), Some(5));
+        assert_eq!(ds.height(), 1);
+
+        let sql = format!(
+            "SELECT -double_amount(amount) AS x FROM {} ORDER BY amount",
+            data()
+        );
+        let ds = query(sql).await.unwrap();
+        let values: Vec<_> = ds.column("x").unwrap().i64().unwrap().into_iter().collect();
+        assert_eq!(values, [Some(-10), Some(-20), Some(-40)]);
+    }
+
+    #[test]
+    fn collect_calls_should_recurse() {
+        register_function("collect_me", |args| Ok(args[0].clone()));
+        let sql = "SELECT a FROM t WHERE a BETWEEN collect_me(1) AND 3 \
+            AND b IN (collect_me(2), 4) \
+            AND CASE WHEN collect_me(c) THEN CAST(collect_me(d) AS INT) ELSE 0 END > 0";
+        let statement = &Parser::parse_sql(&TyrDialect, sql).unwrap()[0];
+        let selection = match statement {
+            Statement::Query(q) => match &q.body {
+                SetExpr::Select(select) => select.selection.clone().unwrap(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let mut calls = Vec::new();
+        collect_calls(&selection, &mut calls);
+        let calls: Vec<_> = calls.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            calls,
+            [
+                "collect_me(1)",
+                "collect_me(2)",
+                "collect_me(c)",
+                "collect_me(d)"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn udf_in_where_should_not_push_limit() {
+        let path = temp_path("udf.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE sales (country TEXT, amount INTEGER);
+             INSERT INTO sales VALUES ('cn', 1), ('us', 2), ('us', 3);",
+        )
+        .unwrap();
+        register_function("upper_country", |args| {
+            let values: Vec<Option<String>> = args[0]
+                .utf8()?
+                .into_iter()
+                .map(|v| v.map(|v| v.to_uppercase()))
+                .collect();
+            Ok(Series::new("", values))
+        });
+
+        // 数据库不认识 upper_country，ORDER BY / LIMIT 下推的话只会返回 cn 那一行
+        let sql = format!(
+            "SELECT amount FROM sqlite://{}?table=sales \
+            WHERE upper_country(country) = 'US' ORDER BY amount LIMIT 1",
+            path.display()
+        );
+        let ds = query(sql).await.unwrap();
+        assert_eq!(ds.height(), 1);
+        assert_eq!(ds.column("amount").unwrap().i64().unwrap().get(0), Some(2));
+    }
+}